@@ -1,27 +1,43 @@
+use std::path::Path;
 use std::process::Command;
 
+// Compiled into `OUT_DIR` as `<shader>.spv`.
+const SHADERS: [&str; 6] = [
+    "shader.vert",
+    "shader.frag",
+    "tonemap.vert",
+    "tonemap.frag",
+    "histogram.comp",
+    "exposure.comp",
+];
+
 fn main() {
-    // Build vertex shader.
-    let output = Command::new("glslc")
-        .arg("resources/shaders/shader.vert")
-        .args(["-o", "src/shader.vert.spirv"])
-        .output()
-        .unwrap();
-    if !output.status.success() {
-        panic!("{}", String::from_utf8_lossy(&output.stderr));
-    }
-    println!("cargo:rerun-if-changed=resources/shaders/shader.vert");
-    println!("cargo:rerun-if-changed=src/shader.vert.spirv");
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-env-changed=PATH");
+
+    for shader in SHADERS {
+        let source = format!("resources/shaders/{shader}");
+        // Used when glslc isn't installed. Regenerate with
+        // `glslc resources/shaders/<shader> -o resources/shaders/spirv/<shader>.spv`
+        // after changing a shader.
+        let precompiled = format!("resources/shaders/spirv/{shader}.spv");
+        let out = Path::new(&out_dir).join(format!("{shader}.spv"));
+        println!("cargo:rerun-if-changed={source}");
+        println!("cargo:rerun-if-changed={precompiled}");
 
-    // Build fragment shader.
-    let output = Command::new("glslc")
-        .arg("resources/shaders/shader.frag")
-        .args(["-o", "src/shader.frag.spirv"])
-        .output()
-        .unwrap();
-    if !output.status.success() {
-        panic!("{}", String::from_utf8_lossy(&output.stderr));
+        match Command::new("glslc")
+            .arg(&source)
+            .arg("-o")
+            .arg(&out)
+            .output()
+        {
+            Ok(output) if output.status.success() => {}
+            // A real compile error, so don't silently use stale SPIR-V.
+            Ok(output) => panic!("{}", String::from_utf8_lossy(&output.stderr)),
+            Err(_) => {
+                std::fs::copy(&precompiled, &out)
+                    .unwrap_or_else(|err| panic!("Could not copy {precompiled}: {err}"));
+            }
+        }
     }
-    println!("cargo:rerun-if-changed=resources/shaders/shader.frag");
-    println!("cargo:rerun-if-changed=src/shader.frag.spirv");
 }
@@ -0,0 +1,105 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D hdr;
+
+layout(std430, set = 0, binding = 1) buffer Exposure {
+    uint histogram[256];
+    float exposure;
+};
+
+layout(push_constant) uniform Constants {
+    float min_log_lum;
+    float log_lum_range;
+    float dt;
+    float exposure_ev;
+    uint tonemap;
+    uint auto_exposure;
+    uint encode_srgb;
+};
+
+layout(location = 0) in vec2 frag_texcoord;
+
+layout(location = 0) out vec4 out_color;
+
+// Stephen Hill's fit of the ACES RRT + ODT.
+vec3 aces_fitted(vec3 color) {
+    const mat3 aces_input = mat3(
+        0.59719, 0.07600, 0.02840,
+        0.35458, 0.90834, 0.13383,
+        0.04823, 0.01566, 0.83777
+    );
+    const mat3 aces_output = mat3(
+        1.60475, -0.10208, -0.00327,
+        -0.53108, 1.10813, -0.07276,
+        -0.07367, -0.00605, 1.07602
+    );
+    color = aces_input * color;
+    vec3 a = color * (color + 0.0245786) - 0.000090537;
+    vec3 b = color * (0.983729 * color + 0.4329510) + 0.238081;
+    return clamp(aces_output * (a / b), 0.0, 1.0);
+}
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// Minimal AgX with the default look.
+vec3 agx(vec3 color) {
+    const mat3 agx_inset = mat3(
+        0.842479062253094, 0.0423282422610123, 0.0423756549057051,
+        0.0784335999999992, 0.878468636469772, 0.0784336,
+        0.0792237451477643, 0.0791661274605434, 0.879142973793104
+    );
+    const mat3 agx_outset = mat3(
+        1.19687900512017, -0.0528968517574562, -0.0529716355144438,
+        -0.0980208811401368, 1.15190312990417, -0.0980434501171241,
+        -0.0990297440797205, -0.0989611768448433, 1.15107367264116
+    );
+    const float min_ev = -12.47393;
+    const float max_ev = 4.026069;
+
+    color = agx_inset * color;
+    color = clamp(log2(max(color, 1e-10)), min_ev, max_ev);
+    color = (color - min_ev) / (max_ev - min_ev);
+
+    // Sigmoid contrast approximation.
+    vec3 x2 = color * color;
+    vec3 x4 = x2 * x2;
+    color = 15.5 * x4 * x2 - 40.14 * x4 * color + 31.96 * x4 - 6.868 * x2 * color
+        + 0.4298 * x2 + 0.1191 * color - 0.00232;
+
+    color = agx_outset * color;
+    return pow(max(color, 0.0), vec3(2.2));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(
+        12.92 * color,
+        1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055,
+        greaterThan(color, vec3(0.0031308))
+    );
+}
+
+void main() {
+    vec3 color = texture(hdr, frag_texcoord).rgb;
+
+    float scale = exp2(exposure_ev);
+    if (auto_exposure != 0) {
+        scale *= exposure;
+    }
+    color *= scale;
+
+    if (tonemap == 0) {
+        color = aces_fitted(color);
+    } else if (tonemap == 1) {
+        color = reinhard(color);
+    } else {
+        color = agx(color);
+    }
+
+    color = clamp(color, 0.0, 1.0);
+    if (encode_srgb != 0) {
+        color = linear_to_srgb(color);
+    }
+    out_color = vec4(color, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 450
+
+layout (location = 0) out vec2 frag_texcoord;
+
+void main() {
+    // Fullscreen triangle.
+    frag_texcoord = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(frag_texcoord * 2.0 - 1.0, 0.0, 1.0);
+}
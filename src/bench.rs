@@ -0,0 +1,247 @@
+use raytrace::profiler::{json_string, CpuFrame, GpuFrame, HeapUsage};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Frames rendered once the scene has loaded, before timing starts.
+const WARMUP_FRAMES: u32 = 10;
+
+/// How long `--bench` times frames for.
+#[derive(Copy, Clone, Debug)]
+pub enum BenchLength {
+    Frames(u32),
+    Duration(Duration),
+}
+
+impl BenchLength {
+    /// Parses a frame count, or seconds with an `s` suffix, e.g. `500` or `10s`.
+    pub fn parse(arg: &str) -> Option<Self> {
+        match arg.strip_suffix('s') {
+            Some(seconds) => seconds
+                .parse::<f64>()
+                .ok()
+                .filter(|&seconds| seconds > 0.)
+                .map(|seconds| Self::Duration(Duration::from_secs_f64(seconds))),
+            None => arg
+                .parse()
+                .ok()
+                .filter(|&frames| frames > 0)
+                .map(Self::Frames),
+        }
+    }
+}
+
+/// Frame times, pass times and memory usage collected by `--bench`, reported as JSON.
+pub struct Bench {
+    length: BenchLength,
+    warmup: u32,
+    started: Option<Instant>,
+    frames: u32,
+    cpu_ms: Vec<f64>,
+    gpu_ms: Vec<f64>,
+    // In the order passes were first seen.
+    passes: Vec<(&'static str, Vec<f64>)>,
+    // Peak totals over every heap, and over device-local heaps.
+    peak_block_bytes: u64,
+    peak_allocation_bytes: u64,
+    peak_device_local_bytes: u64,
+}
+
+impl Bench {
+    pub fn new(length: BenchLength) -> Self {
+        Self {
+            length,
+            warmup: WARMUP_FRAMES,
+            started: None,
+            frames: 0,
+            cpu_ms: Vec::new(),
+            gpu_ms: Vec::new(),
+            passes: Vec::new(),
+            peak_block_bytes: 0,
+            peak_allocation_bytes: 0,
+            peak_device_local_bytes: 0,
+        }
+    }
+
+    /// Counts down the warmup once the scene has loaded, then starts timing.
+    pub fn warm_up(&mut self, loaded: bool) {
+        if self.started.is_some() || !loaded {
+            return;
+        }
+        match self.warmup {
+            0 => self.started = Some(Instant::now()),
+            _ => self.warmup -= 1,
+        }
+    }
+
+    /// Whether frames are being timed.
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn is_done(&self) -> bool {
+        match (self.started, self.length) {
+            (None, _) => false,
+            (Some(_), BenchLength::Frames(frames)) => self.frames >= frames,
+            (Some(started), BenchLength::Duration(duration)) => started.elapsed() >= duration,
+        }
+    }
+
+    /// Records the results of a timed frame. GPU timings arrive a few frames late, so the first
+    /// ones measure the last warmup frames.
+    pub fn add(&mut self, cpu: Option<&CpuFrame>, gpu: Option<&GpuFrame>, memory: &[HeapUsage]) {
+        self.frames += 1;
+        if let Some(cpu) = cpu {
+            self.cpu_ms.push(cpu.duration.as_secs_f64() * 1e3);
+        }
+        if let Some(gpu) = gpu {
+            self.gpu_ms
+                .push(gpu.passes.iter().map(|pass| pass.duration_ms).sum());
+            for pass in &gpu.passes {
+                match self.passes.iter_mut().find(|(name, _)| *name == pass.name) {
+                    Some((_, times)) => times.push(pass.duration_ms),
+                    None => self.passes.push((pass.name, vec![pass.duration_ms])),
+                }
+            }
+        }
+
+        let total = |bytes: fn(&HeapUsage) -> u64, device_local: bool| {
+            memory
+                .iter()
+                .filter(|heap| heap.device_local || !device_local)
+                .map(bytes)
+                .sum::<u64>()
+        };
+        self.peak_block_bytes = self
+            .peak_block_bytes
+            .max(total(|heap| heap.block_bytes, false));
+        self.peak_allocation_bytes = self
+            .peak_allocation_bytes
+            .max(total(|heap| heap.allocation_bytes, false));
+        self.peak_device_local_bytes = self
+            .peak_device_local_bytes
+            .max(total(|heap| heap.block_bytes, true));
+    }
+
+    /// The results as a JSON object. Times are in milliseconds and memory in bytes. `mesh` and
+    /// `texture` are the scene files, or null for the built-in scene.
+    pub fn report(
+        &self,
+        device: &str,
+        mesh: Option<&Path>,
+        texture: Option<&Path>,
+        width: u32,
+        height: u32,
+    ) -> String {
+        let seconds = self
+            .started
+            .map_or(0., |started| started.elapsed().as_secs_f64());
+        let mut json = String::new();
+        let _ = writeln!(json, "{{");
+        let path = |path: Option<&Path>| {
+            path.map_or("null".to_string(), |path| {
+                json_string(&path.to_string_lossy())
+            })
+        };
+        let _ = writeln!(json, "  \"device\": {},", json_string(device));
+        let _ = writeln!(json, "  \"mesh\": {},", path(mesh));
+        let _ = writeln!(json, "  \"texture\": {},", path(texture));
+        let _ = writeln!(json, "  \"width\": {width},");
+        let _ = writeln!(json, "  \"height\": {height},");
+        let _ = writeln!(json, "  \"frames\": {},", self.frames);
+        let _ = writeln!(json, "  \"seconds\": {seconds:.3},");
+        let _ = writeln!(json, "  \"cpu_frame_ms\": {},", summarize(&self.cpu_ms));
+        let _ = writeln!(json, "  \"gpu_frame_ms\": {},", summarize(&self.gpu_ms));
+        let _ = writeln!(json, "  \"gpu_pass_ms\": {{");
+        for (i, (name, times)) in self.passes.iter().enumerate() {
+            let separator = if i + 1 == self.passes.len() { "" } else { "," };
+            let _ = writeln!(
+                json,
+                "    {}: {}{separator}",
+                json_string(name),
+                summarize(times)
+            );
+        }
+        let _ = writeln!(json, "  }},");
+        let _ = writeln!(json, "  \"memory\": {{");
+        let _ = writeln!(json, "    \"peak_block_bytes\": {},", self.peak_block_bytes);
+        let _ = writeln!(
+            json,
+            "    \"peak_allocation_bytes\": {},",
+            self.peak_allocation_bytes
+        );
+        let _ = writeln!(
+            json,
+            "    \"peak_device_local_block_bytes\": {}",
+            self.peak_device_local_bytes
+        );
+        let _ = writeln!(json, "  }}");
+        let _ = writeln!(json, "}}");
+        json
+    }
+}
+
+// Min, average, 95th and 99th percentile and max of `times`, or null without any.
+fn summarize(times: &[f64]) -> String {
+    if times.is_empty() {
+        return "null".to_string();
+    }
+    let mut sorted = times.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    // Nearest rank.
+    let percentile = |p: f64| sorted[((p * sorted.len() as f64).ceil() as usize).max(1) - 1];
+    format!(
+        "{{\"min\": {:.3}, \"avg\": {:.3}, \"p95\": {:.3}, \"p99\": {:.3}, \"max\": {:.3}}}",
+        sorted[0],
+        sorted.iter().sum::<f64>() / sorted.len() as f64,
+        percentile(0.95),
+        percentile(0.99),
+        sorted[sorted.len() - 1],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_length() {
+        assert!(matches!(
+            BenchLength::parse("500"),
+            Some(BenchLength::Frames(500))
+        ));
+        assert!(matches!(
+            BenchLength::parse("10s"),
+            Some(BenchLength::Duration(duration)) if duration == Duration::from_secs(10)
+        ));
+        assert!(matches!(
+            BenchLength::parse("0.5s"),
+            Some(BenchLength::Duration(duration)) if duration == Duration::from_millis(500)
+        ));
+        for arg in [
+            "", "s", "0", "0s", "-1", "-1s", "1.5", "10ms", "10 s", "ten",
+        ] {
+            assert!(BenchLength::parse(arg).is_none(), "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn summarize_percentiles() {
+        assert_eq!(summarize(&[]), "null");
+        assert_eq!(
+            summarize(&[2.5]),
+            "{\"min\": 2.500, \"avg\": 2.500, \"p95\": 2.500, \"p99\": 2.500, \"max\": 2.500}"
+        );
+        // Unsorted, to check ranks are taken in order.
+        let times = (1..=20).rev().map(f64::from).collect::<Vec<_>>();
+        assert_eq!(
+            summarize(&times),
+            "{\"min\": 1.000, \"avg\": 10.500, \"p95\": 19.000, \"p99\": 20.000, \"max\": 20.000}"
+        );
+        let times = (1..=100).rev().map(f64::from).collect::<Vec<_>>();
+        assert_eq!(
+            summarize(&times),
+            "{\"min\": 1.000, \"avg\": 50.500, \"p95\": 95.000, \"p99\": 99.000, \"max\": 100.000}"
+        );
+    }
+}
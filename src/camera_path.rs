@@ -0,0 +1,263 @@
+use glam::{Mat4, Quat, Vec3};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Copy, Clone, Debug)]
+pub struct CameraPose {
+    pub time: f32,
+    pub position: Vec3,
+    pub orientation: Quat,
+}
+
+impl CameraPose {
+    pub fn view(&self) -> Mat4 {
+        Mat4::from_quat(self.orientation) * Mat4::from_translation(self.position)
+    }
+}
+
+/// Timestamped camera poses, stored one per line as `time px py pz qx qy qz qw`.
+#[derive(Default)]
+pub struct CameraPath {
+    keys: Vec<CameraPose>,
+}
+
+impl CameraPath {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        let invalid = |line: usize| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}:{line}: expected 8 numbers", path.display()),
+            )
+        };
+
+        let mut keys = Vec::new();
+        for (i, line) in BufReader::new(std::fs::File::open(path)?)
+            .lines()
+            .enumerate()
+        {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let v = line
+                .split_whitespace()
+                .map(|x| x.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid(i + 1))?;
+            let [time, px, py, pz, qx, qy, qz, qw] = v[..] else {
+                return Err(invalid(i + 1));
+            };
+            keys.push(CameraPose {
+                time,
+                position: Vec3::new(px, py, pz),
+                orientation: Quat::from_xyzw(qx, qy, qz, qw).normalize(),
+            });
+        }
+
+        if keys.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{}: no camera keys", path.display()),
+            ));
+        }
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        Ok(Self { keys })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "# time px py pz qx qy qz qw")?;
+        for key in &self.keys {
+            let (p, q) = (key.position, key.orientation);
+            writeln!(
+                file,
+                "{} {} {} {} {} {} {} {}",
+                key.time, p.x, p.y, p.z, q.x, q.y, q.z, q.w
+            )?;
+        }
+        file.flush()
+    }
+
+    pub fn push(&mut self, pose: CameraPose) {
+        self.keys.push(pose);
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keys.last().map_or(0.0, |key| key.time)
+    }
+
+    /// Interpolates the pose at `time`, Catmull-Rom for position and slerp for orientation.
+    /// Times outside the path are clamped to its ends.
+    pub fn sample(&self, time: f32) -> CameraPose {
+        let keys = &self.keys;
+        let next = keys.partition_point(|key| key.time <= time);
+        if next == 0 {
+            return CameraPose { time, ..keys[0] };
+        }
+        if next == keys.len() {
+            return CameraPose {
+                time,
+                ..keys[keys.len() - 1]
+            };
+        }
+
+        let (i1, i2) = (next - 1, next);
+        let (k0, k1) = (keys[i1.saturating_sub(1)], keys[i1]);
+        let (k2, k3) = (keys[i2], keys[(i2 + 1).min(keys.len() - 1)]);
+        let span = k2.time - k1.time;
+        let t = if span > 0.0 {
+            (time - k1.time) / span
+        } else {
+            0.0
+        };
+
+        CameraPose {
+            time,
+            position: catmull_rom(k0.position, k1.position, k2.position, k3.position, t),
+            orientation: k1.orientation.slerp(k2.orientation, t),
+        }
+    }
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(time: f32, x: f32, yaw: f32) -> CameraPose {
+        CameraPose {
+            time,
+            position: Vec3::new(x, x * x, -x),
+            orientation: Quat::from_rotation_y(yaw),
+        }
+    }
+
+    fn camera_path(keys: &[CameraPose]) -> CameraPath {
+        CameraPath {
+            keys: keys.to_vec(),
+        }
+    }
+
+    fn assert_pose_eq(a: CameraPose, b: CameraPose) {
+        assert_eq!(a.time, b.time);
+        assert!(a.position.abs_diff_eq(b.position, 1e-5), "{a:?} != {b:?}");
+        assert!(
+            a.orientation.abs_diff_eq(b.orientation, 1e-5),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn clamps_to_ends() {
+        let keys = [
+            pose(1.0, 1.0, 0.1),
+            pose(2.0, 3.0, 0.5),
+            pose(4.0, -2.0, 1.0),
+        ];
+        let path = camera_path(&keys);
+        assert_pose_eq(
+            path.sample(-5.0),
+            CameraPose {
+                time: -5.0,
+                ..keys[0]
+            },
+        );
+        assert_pose_eq(path.sample(1.0), keys[0]);
+        assert_pose_eq(path.sample(4.0), keys[2]);
+        assert_pose_eq(
+            path.sample(9.0),
+            CameraPose {
+                time: 9.0,
+                ..keys[2]
+            },
+        );
+    }
+
+    #[test]
+    fn passes_through_keys() {
+        let keys = [
+            pose(0.0, 0.0, 0.0),
+            pose(1.0, 2.0, 0.3),
+            pose(1.5, 1.0, 0.7),
+            pose(3.0, 4.0, 1.2),
+        ];
+        let path = camera_path(&keys);
+        for key in keys {
+            assert_pose_eq(path.sample(key.time), key);
+            // Approaching a key from the left lands on it too.
+            let before = path.sample(key.time - 1e-4);
+            if key.time > 0.0 {
+                assert!(before.position.abs_diff_eq(key.position, 1e-2));
+            }
+        }
+
+        // Each segment starts and ends on its inner points.
+        let [p0, p1, p2, p3] = [0.0, 2.0, 1.0, 4.0].map(|x| Vec3::new(x, -x, 1.0));
+        assert_eq!(catmull_rom(p0, p1, p2, p3, 0.0), p1);
+        assert!(catmull_rom(p0, p1, p2, p3, 1.0).abs_diff_eq(p2, 1e-6));
+    }
+
+    #[test]
+    fn duplicate_times() {
+        let keys = [
+            pose(0.0, 0.0, 0.0),
+            pose(1.0, 1.0, 0.5),
+            pose(1.0, 5.0, 1.0),
+            pose(2.0, 2.0, 1.5),
+        ];
+        let path = camera_path(&keys);
+        // The later key wins at the shared time.
+        assert_pose_eq(path.sample(1.0), keys[2]);
+        for time in [0.5, 0.999, 1.001, 1.5] {
+            let sample = path.sample(time);
+            assert!(sample.position.is_finite() && sample.orientation.is_finite());
+        }
+
+        // Every key at once.
+        let path = camera_path(&[pose(1.0, 1.0, 0.5), pose(1.0, 5.0, 1.0)]);
+        assert_pose_eq(
+            path.sample(0.0),
+            CameraPose {
+                time: 0.0,
+                ..keys[1]
+            },
+        );
+        assert_pose_eq(
+            path.sample(1.0),
+            CameraPose {
+                time: 1.0,
+                ..keys[2]
+            },
+        );
+    }
+
+    #[test]
+    fn save_load_round_trip() {
+        let keys = [
+            pose(0.0, 0.0, 0.0),
+            pose(0.25, 1.0 / 3.0, 0.3),
+            pose(1.75, -7.5, 2.9),
+        ];
+        let file = std::env::temp_dir().join(format!("camera-path-{}.txt", std::process::id()));
+        camera_path(&keys).save(&file).unwrap();
+        let loaded = CameraPath::load(&file);
+        std::fs::remove_file(&file).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.keys.len(), keys.len());
+        for (&loaded, &key) in loaded.keys.iter().zip(&keys) {
+            assert_pose_eq(loaded, key);
+        }
+        assert_eq!(loaded.duration(), 1.75);
+    }
+}
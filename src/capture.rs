@@ -0,0 +1,109 @@
+use ash::vk;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::context::SyncPoint;
+use crate::error::{Error, Result};
+use crate::staging::{ImageTarget, Readback, ReadbackBuffer};
+
+/// Copies of swapchain images read back through a [`ReadbackBuffer`], used for screenshots.
+pub struct FrameCapture {
+    extent: vk::Extent2D,
+    format: vk::Format,
+    pending: Option<Readback<u8>>,
+}
+
+impl FrameCapture {
+    pub fn new(extent: vk::Extent2D, format: vk::Format) -> Self {
+        Self {
+            extent,
+            format,
+            pending: None,
+        }
+    }
+
+    /// Records a copy of `image`, which must be in `TRANSFER_SRC_OPTIMAL` with its writes visible
+    /// to transfers, into `readback`. `done` must be signaled by the submission of
+    /// `command_buffer`.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        done: SyncPoint,
+        readback: &mut ReadbackBuffer,
+        image: vk::Image,
+    ) -> Result<()> {
+        let copy = readback.read_image(
+            device,
+            command_buffer,
+            done,
+            &ImageTarget {
+                image,
+                format: self.format,
+                width: self.extent.width,
+                height: self.extent.height,
+                mip_levels: 1,
+                array_layers: 1,
+            },
+            0,
+            0,
+        );
+
+        if let Some(old) = self.pending.replace(copy?) {
+            readback.release(device, old)?;
+        }
+        Ok(())
+    }
+
+    /// Whether frames in `format` can be converted to RGBA8.
+    pub fn supports(format: vk::Format) -> bool {
+        matches!(
+            format,
+            vk::Format::B8G8R8A8_UNORM
+                | vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_UNORM
+                | vk::Format::R8G8B8A8_SRGB
+        )
+    }
+
+    /// Waits for the last recorded copy and returns it as tightly packed RGBA8.
+    pub unsafe fn read_rgba(
+        &mut self,
+        device: &ash::Device,
+        readback: &mut ReadbackBuffer,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(copy) = self.pending.take() else {
+            return Ok(None);
+        };
+        let mut pixels = readback.read(device, &copy)?;
+        readback.release(device, copy)?;
+
+        match self.format {
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => {
+                pixels.chunks_exact_mut(4).for_each(|px| px.swap(0, 2))
+            }
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => {}
+            format => return Err(Error::UnsupportedFormat(format)),
+        }
+
+        // The swapchain alpha is meaningless with an opaque composite.
+        pixels.chunks_exact_mut(4).for_each(|px| px[3] = 255);
+        Ok(Some(pixels))
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+}
+
+/// Writes tightly packed RGBA8 pixels to a PNG file.
+pub fn save_png(path: &Path, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
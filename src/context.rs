@@ -0,0 +1,492 @@
+use ash::vk::{self, Handle};
+use ash::{ext, khr, Entry};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::panic::Location;
+use std::rc::{Rc, Weak};
+
+use crate::debug::Messenger;
+use crate::device::Queues;
+use crate::error::{Check, Error, Result};
+
+/// Instance, device and allocator shared by every owned Vulkan object.
+///
+/// Objects hold an `Rc<Context>`, so the device is only destroyed once the last of them is.
+pub struct Context {
+    pub entry: Entry,
+    pub instance: ash::Instance,
+    pub device: ash::Device,
+    pub pdevice: vk::PhysicalDevice,
+    pub queues: Queues,
+    pub allocator: ManuallyDrop<vk_mem::Allocator>,
+    pub surface_instance: khr::surface::Instance,
+    pub swapchain_device: khr::swapchain::Device,
+    /// Set when validation is enabled.
+    messenger: Option<Messenger>,
+    debug_utils: Option<ext::debug_utils::Device>,
+    /// Live objects and where they were created, when leak tracking is on.
+    live: Option<RefCell<HashMap<(&'static str, u64), &'static Location<'static>>>>,
+}
+
+impl Context {
+    /// Takes ownership of the instance and device. If this fails, they are destroyed along with
+    /// `messenger` and `surface`; otherwise `surface` is left to the caller.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn new(
+        entry: Entry,
+        instance: ash::Instance,
+        device: ash::Device,
+        pdevice: vk::PhysicalDevice,
+        queues: Queues,
+        messenger: Option<Messenger>,
+        surface: Option<vk::SurfaceKHR>,
+        track_objects: bool,
+    ) -> Result<Rc<Self>> {
+        let allocator = match vk_mem::Allocator::new(vk_mem::AllocatorCreateInfo::new(
+            &instance, &device, pdevice,
+        )) {
+            Ok(allocator) => allocator,
+            Err(result) => {
+                device.destroy_device(None);
+                if let Some(surface) = surface {
+                    khr::surface::Instance::new(&entry, &instance).destroy_surface(surface, None);
+                }
+                if let Some(messenger) = &messenger {
+                    messenger.destroy();
+                }
+                instance.destroy_instance(None);
+                return Err(Error::vulkan("vmaCreateAllocator", result));
+            }
+        };
+        Ok(Rc::new(Self {
+            surface_instance: khr::surface::Instance::new(&entry, &instance),
+            swapchain_device: khr::swapchain::Device::new(&instance, &device),
+            debug_utils: messenger
+                .is_some()
+                .then(|| ext::debug_utils::Device::new(&instance, &device)),
+            messenger,
+            entry,
+            instance,
+            device,
+            pdevice,
+            queues,
+            allocator: ManuallyDrop::new(allocator),
+            live: track_objects.then(Default::default),
+        }))
+    }
+
+    /// Returns a guard that reports objects still alive when it is dropped. Declare it right
+    /// after the context, so everything declared later is dropped first.
+    pub fn leak_check(self: &Rc<Self>) -> LeakCheck {
+        LeakCheck(Rc::downgrade(self))
+    }
+
+    /// Names `handle` in validation messages and debuggers. Does nothing without validation.
+    pub unsafe fn set_name(&self, handle: impl Handle, name: &str) -> Result<()> {
+        let Some(debug_utils) = &self.debug_utils else {
+            return Ok(());
+        };
+        let name = CString::new(name).unwrap_or_default();
+        debug_utils
+            .set_debug_utils_object_name(
+                &vk::DebugUtilsObjectNameInfoEXT::default()
+                    .object_handle(handle)
+                    .object_name(&name),
+            )
+            .check("vkSetDebugUtilsObjectNameEXT")
+    }
+
+    #[track_caller]
+    fn track(&self, kind: &'static str, raw: u64) {
+        if let Some(live) = &self.live {
+            live.borrow_mut().insert((kind, raw), Location::caller());
+        }
+    }
+
+    fn untrack(&self, kind: &'static str, raw: u64) {
+        if let Some(live) = &self.live {
+            live.borrow_mut().remove(&(kind, raw));
+        }
+    }
+
+    #[track_caller]
+    pub unsafe fn create_fence(self: &Rc<Self>, signaled: bool) -> Result<Fence> {
+        let flags = if signaled {
+            vk::FenceCreateFlags::SIGNALED
+        } else {
+            vk::FenceCreateFlags::empty()
+        };
+        let fence = self
+            .device
+            .create_fence(&vk::FenceCreateInfo::default().flags(flags), None)
+            .check("vkCreateFence")?;
+        Ok(Owned::new(self, fence))
+    }
+
+    #[track_caller]
+    pub unsafe fn create_semaphore(self: &Rc<Self>) -> Result<Semaphore> {
+        let semaphore = self
+            .device
+            .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+            .check("vkCreateSemaphore")?;
+        Ok(Owned::new(self, semaphore))
+    }
+
+    #[track_caller]
+    pub unsafe fn create_timeline_semaphore(
+        self: &Rc<Self>,
+        initial_value: u64,
+    ) -> Result<Semaphore> {
+        let semaphore = self
+            .device
+            .create_semaphore(
+                &vk::SemaphoreCreateInfo::default().push_next(
+                    &mut vk::SemaphoreTypeCreateInfo::default()
+                        .semaphore_type(vk::SemaphoreType::TIMELINE)
+                        .initial_value(initial_value),
+                ),
+                None,
+            )
+            .check("vkCreateSemaphore")?;
+        Ok(Owned::new(self, semaphore))
+    }
+
+    #[track_caller]
+    pub unsafe fn create_graphics_pipeline(
+        self: &Rc<Self>,
+        cache: vk::PipelineCache,
+        info: &vk::GraphicsPipelineCreateInfo,
+    ) -> Result<Pipeline> {
+        let created =
+            self.device
+                .create_graphics_pipelines(cache, std::slice::from_ref(info), None);
+        self.own_pipeline(created, "vkCreateGraphicsPipelines")
+    }
+
+    #[track_caller]
+    pub unsafe fn create_compute_pipeline(
+        self: &Rc<Self>,
+        cache: vk::PipelineCache,
+        info: &vk::ComputePipelineCreateInfo,
+    ) -> Result<Pipeline> {
+        let created = self
+            .device
+            .create_compute_pipelines(cache, std::slice::from_ref(info), None);
+        self.own_pipeline(created, "vkCreateComputePipelines")
+    }
+
+    // Failed pipeline creation still returns the pipelines that were created, which have to be
+    // destroyed; the rest are null.
+    #[track_caller]
+    unsafe fn own_pipeline(
+        self: &Rc<Self>,
+        created: std::result::Result<Vec<vk::Pipeline>, (Vec<vk::Pipeline>, vk::Result)>,
+        call: &'static str,
+    ) -> Result<Pipeline> {
+        match created {
+            Ok(pipelines) => Ok(Owned::new(self, pipelines[0])),
+            Err((pipelines, result)) => {
+                for pipeline in pipelines {
+                    self.device.destroy_pipeline(pipeline, None);
+                }
+                Err(Error::vulkan(call, result))
+            }
+        }
+    }
+
+    #[track_caller]
+    pub unsafe fn create_shader_module(self: &Rc<Self>, spirv: &[u8]) -> Result<ShaderModule> {
+        // Copy into words, as byte slices aren't necessarily aligned for them.
+        let code = ash::util::read_spv(&mut std::io::Cursor::new(spirv))?;
+        let module = self
+            .device
+            .create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&code), None)
+            .check("vkCreateShaderModule")?;
+        Ok(Owned::new(self, module))
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        unsafe {
+            ManuallyDrop::drop(&mut self.allocator);
+            self.device.destroy_device(None);
+            if let Some(messenger) = &self.messenger {
+                messenger.destroy();
+            }
+            self.instance.destroy_instance(None);
+        }
+    }
+}
+
+/// A value of a timeline semaphore, reached once the submission signalling it has finished.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SyncPoint {
+    pub semaphore: vk::Semaphore,
+    pub value: u64,
+}
+
+impl SyncPoint {
+    pub unsafe fn is_reached(&self, device: &ash::Device) -> Result<bool> {
+        Ok(device
+            .get_semaphore_counter_value(self.semaphore)
+            .check("vkGetSemaphoreCounterValue")?
+            >= self.value)
+    }
+
+    pub unsafe fn wait(&self, device: &ash::Device) -> Result<()> {
+        device
+            .wait_semaphores(
+                &vk::SemaphoreWaitInfo::default()
+                    .semaphores(&[self.semaphore])
+                    .values(&[self.value]),
+                u64::MAX,
+            )
+            .check("vkWaitSemaphores")
+    }
+}
+
+/// See [`Context::leak_check`].
+pub struct LeakCheck(Weak<Context>);
+
+impl Drop for LeakCheck {
+    fn drop(&mut self) {
+        let Some(ctx) = self.0.upgrade() else {
+            return;
+        };
+        // Our upgrade and the owner's handle.
+        let holders = Rc::strong_count(&ctx) - 2;
+        if holders == 0 {
+            return;
+        }
+
+        println!("Warning: {holders} objects still hold the Vulkan context at shutdown.");
+        if let Some(live) = &ctx.live {
+            let live = live.borrow();
+            let mut leaks = live.iter().collect::<Vec<_>>();
+            leaks.sort_by_key(|(_, location)| (location.file(), location.line()));
+            for ((kind, raw), location) in leaks {
+                println!("  {kind} {raw:#x} created at {location}");
+            }
+        }
+    }
+}
+
+/// A Vulkan object destroyed through the [`Context`] that created it.
+pub trait DeviceObject: Handle + Copy {
+    unsafe fn destroy(self, ctx: &Context);
+}
+
+macro_rules! device_objects {
+    ($($ty:ident => |$handle:ident, $ctx:ident| $destroy:expr;)*) => {
+        $(
+            impl DeviceObject for vk::$ty {
+                unsafe fn destroy(self, $ctx: &Context) {
+                    let $handle = self;
+                    $destroy
+                }
+            }
+            pub type $ty = Owned<vk::$ty>;
+        )*
+    };
+}
+
+device_objects! {
+    ImageView => |view, ctx| ctx.device.destroy_image_view(view, None);
+    Sampler => |sampler, ctx| ctx.device.destroy_sampler(sampler, None);
+    Pipeline => |pipeline, ctx| ctx.device.destroy_pipeline(pipeline, None);
+    PipelineCache => |cache, ctx| ctx.device.destroy_pipeline_cache(cache, None);
+    PipelineLayout => |layout, ctx| ctx.device.destroy_pipeline_layout(layout, None);
+    DescriptorSetLayout => |layout, ctx| ctx.device.destroy_descriptor_set_layout(layout, None);
+    DescriptorPool => |pool, ctx| ctx.device.destroy_descriptor_pool(pool, None);
+    ShaderModule => |module, ctx| ctx.device.destroy_shader_module(module, None);
+    Semaphore => |semaphore, ctx| ctx.device.destroy_semaphore(semaphore, None);
+    Fence => |fence, ctx| ctx.device.destroy_fence(fence, None);
+    QueryPool => |pool, ctx| ctx.device.destroy_query_pool(pool, None);
+    CommandPool => |pool, ctx| ctx.device.destroy_command_pool(pool, None);
+    SwapchainKHR => |swapchain, ctx| ctx.swapchain_device.destroy_swapchain(swapchain, None);
+    SurfaceKHR => |surface, ctx| ctx.surface_instance.destroy_surface(surface, None);
+}
+
+/// Owning handle to a Vulkan object, destroyed on drop.
+pub struct Owned<T: DeviceObject> {
+    ctx: Rc<Context>,
+    handle: T,
+}
+
+impl<T: DeviceObject> Owned<T> {
+    /// Takes ownership of `handle`, which must have been created from `ctx`.
+    #[track_caller]
+    pub fn new(ctx: &Rc<Context>, handle: T) -> Self {
+        ctx.track(std::any::type_name::<T>(), handle.as_raw());
+        Self {
+            ctx: ctx.clone(),
+            handle,
+        }
+    }
+}
+
+impl<T: DeviceObject> Deref for Owned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.handle
+    }
+}
+
+impl<T: DeviceObject> Drop for Owned<T> {
+    fn drop(&mut self) {
+        self.ctx
+            .untrack(std::any::type_name::<T>(), self.handle.as_raw());
+        unsafe { self.handle.destroy(&self.ctx) }
+    }
+}
+
+/// A buffer and its memory.
+pub struct Buffer {
+    ctx: Rc<Context>,
+    buffer: vk::Buffer,
+    alloc: vk_mem::Allocation,
+    map: Option<*mut u8>,
+}
+
+impl Buffer {
+    #[track_caller]
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        info: &vk::BufferCreateInfo,
+        alloc_info: &vk_mem::AllocationCreateInfo,
+    ) -> Result<Self> {
+        use vk_mem::Alloc;
+        let (buffer, alloc) = ctx
+            .allocator
+            .create_buffer(info, alloc_info)
+            .check("vmaCreateBuffer")?;
+        ctx.track(std::any::type_name::<vk::Buffer>(), buffer.as_raw());
+        Ok(Self {
+            ctx: ctx.clone(),
+            buffer,
+            alloc,
+            map: None,
+        })
+    }
+
+    /// Maps the buffer for the rest of its life. It must be host visible.
+    pub unsafe fn map(&mut self) -> Result<*mut u8> {
+        if let Some(map) = self.map {
+            return Ok(map);
+        }
+        let map = self
+            .ctx
+            .allocator
+            .map_memory(&mut self.alloc)
+            .check("vmaMapMemory")?;
+        self.map = Some(map);
+        Ok(map)
+    }
+
+    /// Makes device writes to `offset..offset + size` visible to mapped reads.
+    pub unsafe fn invalidate(&self, offset: u64, size: u64) -> Result<()> {
+        self.ctx
+            .allocator
+            .invalidate_allocation(&self.alloc, offset, size)
+            .check("vmaInvalidateAllocation")
+    }
+}
+
+impl Deref for Buffer {
+    type Target = vk::Buffer;
+
+    fn deref(&self) -> &vk::Buffer {
+        &self.buffer
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        self.ctx
+            .untrack(std::any::type_name::<vk::Buffer>(), self.buffer.as_raw());
+        unsafe {
+            if self.map.is_some() {
+                self.ctx.allocator.unmap_memory(&mut self.alloc);
+            }
+            self.ctx
+                .allocator
+                .destroy_buffer(self.buffer, &mut self.alloc);
+        }
+    }
+}
+
+/// An image and its memory.
+pub struct Image {
+    ctx: Rc<Context>,
+    image: vk::Image,
+    alloc: vk_mem::Allocation,
+}
+
+impl Image {
+    #[track_caller]
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        info: &vk::ImageCreateInfo,
+        alloc_info: &vk_mem::AllocationCreateInfo,
+    ) -> Result<Self> {
+        use vk_mem::Alloc;
+        let (image, alloc) = ctx
+            .allocator
+            .create_image(info, alloc_info)
+            .check("vmaCreateImage")?;
+        ctx.track(std::any::type_name::<vk::Image>(), image.as_raw());
+        Ok(Self {
+            ctx: ctx.clone(),
+            image,
+            alloc,
+        })
+    }
+
+    /// Creates a view of `subresource_range`. The view must be dropped before the image.
+    #[track_caller]
+    pub unsafe fn create_view(
+        &self,
+        view_type: vk::ImageViewType,
+        format: vk::Format,
+        subresource_range: vk::ImageSubresourceRange,
+    ) -> Result<ImageView> {
+        let view = self
+            .ctx
+            .device
+            .create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .image(self.image)
+                    .view_type(view_type)
+                    .format(format)
+                    .subresource_range(subresource_range),
+                None,
+            )
+            .check("vkCreateImageView")?;
+        Ok(Owned::new(&self.ctx, view))
+    }
+}
+
+impl Deref for Image {
+    type Target = vk::Image;
+
+    fn deref(&self) -> &vk::Image {
+        &self.image
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        self.ctx
+            .untrack(std::any::type_name::<vk::Image>(), self.image.as_raw());
+        unsafe {
+            self.ctx
+                .allocator
+                .destroy_image(self.image, &mut self.alloc);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use ash::{ext, vk, Entry};
+use std::ffi::{c_void, CStr};
+
+use crate::error::{Check, Result};
+
+pub type Severity = vk::DebugUtilsMessageSeverityFlagsEXT;
+
+/// Parses a severity name as used on the command line, e.g. `warning`.
+pub fn parse_severity(name: &str) -> Option<Severity> {
+    match name {
+        "verbose" => Some(Severity::VERBOSE),
+        "info" => Some(Severity::INFO),
+        "warning" => Some(Severity::WARNING),
+        "error" => Some(Severity::ERROR),
+        _ => None,
+    }
+}
+
+/// Routes validation messages of at least a given severity into our log.
+pub struct Messenger {
+    debug_utils: ext::debug_utils::Instance,
+    messenger: vk::DebugUtilsMessengerEXT,
+}
+
+impl Messenger {
+    pub unsafe fn new(
+        entry: &Entry,
+        instance: &ash::Instance,
+        min_severity: Severity,
+    ) -> Result<Self> {
+        let debug_utils = ext::debug_utils::Instance::new(entry, instance);
+        let severities = [
+            Severity::VERBOSE,
+            Severity::INFO,
+            Severity::WARNING,
+            Severity::ERROR,
+        ]
+        .into_iter()
+        .filter(|&severity| severity.as_raw() >= min_severity.as_raw())
+        .fold(Severity::empty(), |mask, severity| mask | severity);
+        let messenger = debug_utils
+            .create_debug_utils_messenger(
+                &vk::DebugUtilsMessengerCreateInfoEXT::default()
+                    .message_severity(severities)
+                    .message_type(
+                        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
+                            | vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
+                            | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
+                    )
+                    .pfn_user_callback(Some(log_message)),
+                None,
+            )
+            .check("vkCreateDebugUtilsMessengerEXT")?;
+        Ok(Self {
+            debug_utils,
+            messenger,
+        })
+    }
+
+    /// Must be called before the instance is destroyed.
+    pub unsafe fn destroy(&self) {
+        self.debug_utils
+            .destroy_debug_utils_messenger(self.messenger, None);
+    }
+}
+
+unsafe extern "system" fn log_message(
+    severity: Severity,
+    types: vk::DebugUtilsMessageTypeFlagsEXT,
+    data: *const vk::DebugUtilsMessengerCallbackDataEXT<'_>,
+    _user_data: *mut c_void,
+) -> vk::Bool32 {
+    let level = match severity {
+        Severity::ERROR => "error",
+        Severity::WARNING => "warning",
+        Severity::INFO => "info",
+        _ => "verbose",
+    };
+    let message = data
+        .as_ref()
+        .and_then(|data| data.message_as_c_str())
+        .map_or("".into(), CStr::to_string_lossy);
+    println!("[vulkan {level} {types:?}] {message}");
+    // Never abort the call that triggered the message.
+    vk::FALSE
+}
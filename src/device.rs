@@ -0,0 +1,314 @@
+use ash::{ext, khr, vk, Entry};
+use itertools::Itertools;
+use std::ffi::CStr;
+use std::rc::Rc;
+use winit::raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+
+use crate::context::{Context, Owned, SurfaceKHR};
+use crate::debug::{Messenger, Severity};
+use crate::error::{Check, Error, Result};
+use crate::staging::QueueTransfer;
+
+// Required Vulkan features.
+const INSTANCE_EXTENSIONS: [&CStr; 0] = [];
+const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+const DEVICE_EXTENSIONS: [&CStr; 4] = [
+    c"VK_KHR_dynamic_rendering",
+    c"VK_EXT_descriptor_indexing",
+    c"VK_KHR_synchronization2",
+    c"VK_KHR_swapchain",
+];
+
+/// Queue families picked for the device, and their queues.
+#[derive(Copy, Clone, Debug)]
+pub struct Queues {
+    /// Capable of both present and graphics commands.
+    pub graphics_family: u32,
+    /// A transfer-only family when there is one, otherwise the graphics family.
+    pub transfer_family: u32,
+    pub graphics: vk::Queue,
+    pub present: vk::Queue,
+    pub transfer: vk::Queue,
+}
+
+impl Queues {
+    /// Ownership transfer needed by uploads, if they run on a separate family.
+    pub fn queue_transfer(&self) -> Option<QueueTransfer> {
+        (self.transfer_family != self.graphics_family).then_some(QueueTransfer {
+            src_family: self.transfer_family,
+            dst_family: self.graphics_family,
+        })
+    }
+}
+
+/// Creates the instance, a surface for the window and a device that can present to it. Without
+/// a window, no surface is created and any device with a graphics queue will do.
+///
+/// With `validation`, the validation layers are enabled if installed, and their messages of at
+/// least that severity are logged.
+pub unsafe fn create(
+    window: Option<(RawDisplayHandle, RawWindowHandle)>,
+    validation: Option<Severity>,
+    track_objects: bool,
+) -> Result<(Rc<Context>, Option<SurfaceKHR>)> {
+    let entry = Entry::load()?;
+
+    let validation = match validation {
+        Some(severity) => {
+            let installed = entry
+                .enumerate_instance_layer_properties()
+                .check("vkEnumerateInstanceLayerProperties")?
+                .iter()
+                .any(|layer| layer.layer_name_as_c_str() == Ok(VALIDATION_LAYER));
+            if !installed {
+                println!("{VALIDATION_LAYER:?} is not installed, continuing without validation.");
+            }
+            installed.then_some(severity)
+        }
+        None => None,
+    };
+
+    let instance = {
+        let required_extensions = match window {
+            Some((display_handle, _)) => ash_window::enumerate_required_extensions(display_handle)
+                .check("vkEnumerateInstanceExtensionProperties")?,
+            None => &[],
+        };
+        let mut extensions = [
+            required_extensions,
+            &INSTANCE_EXTENSIONS.map(|x: &CStr| x.as_ptr()),
+        ]
+        .concat();
+        let mut supported = entry
+            .enumerate_instance_extension_properties(None)
+            .check("vkEnumerateInstanceExtensionProperties")?;
+        let mut layers = Vec::new();
+        if validation.is_some() {
+            // The layer provides debug utils itself.
+            supported.extend(
+                entry
+                    .enumerate_instance_extension_properties(Some(VALIDATION_LAYER))
+                    .check("vkEnumerateInstanceExtensionProperties")?,
+            );
+            extensions.push(ext::debug_utils::NAME.as_ptr());
+            layers.push(VALIDATION_LAYER.as_ptr());
+        }
+        require_extensions(
+            extensions.iter().map(|&name| CStr::from_ptr(name)),
+            &supported,
+        )?;
+
+        let app_info = vk::ApplicationInfo::default()
+            .application_name(c"Raytrace")
+            .api_version(vk::make_api_version(0, 1, 3, 0));
+        let instance_cinfo = vk::InstanceCreateInfo::default()
+            .application_info(&app_info)
+            .enabled_layer_names(&layers)
+            .enabled_extension_names(&extensions);
+        entry
+            .create_instance(&instance_cinfo, None)
+            .check("vkCreateInstance")?
+    };
+    // Until the context owns them, the instance and what is created from it are destroyed by
+    // hand on errors.
+    let messenger = match validation
+        .map(|severity| Messenger::new(&entry, &instance, severity))
+        .transpose()
+    {
+        Ok(messenger) => messenger,
+        Err(err) => {
+            destroy_instance(&instance, None, None);
+            return Err(err);
+        }
+    };
+    let surface_instance = khr::surface::Instance::new(&entry, &instance);
+    let surface = match window
+        .map(|(display_handle, window_handle)| {
+            ash_window::create_surface(&entry, &instance, display_handle, window_handle, None)
+                .check("vkCreateSurfaceKHR")
+        })
+        .transpose()
+    {
+        Ok(surface) => surface,
+        Err(err) => {
+            destroy_instance(&instance, messenger.as_ref(), None);
+            return Err(err);
+        }
+    };
+    let (pdevice, device, queues) = match create_device(&instance, &surface_instance, surface) {
+        Ok(created) => created,
+        Err(err) => {
+            let surface = surface.map(|surface| (&surface_instance, surface));
+            destroy_instance(&instance, messenger.as_ref(), surface);
+            return Err(err);
+        }
+    };
+
+    let ctx = Context::new(
+        entry,
+        instance,
+        device,
+        pdevice,
+        queues,
+        messenger,
+        surface,
+        track_objects,
+    )?;
+    let surface = surface.map(|surface| Owned::new(&ctx, surface));
+    Ok((ctx, surface))
+}
+
+// Picks a physical device that can draw, and present to `surface` if there is one, and creates
+// a device with its queues.
+unsafe fn create_device(
+    instance: &ash::Instance,
+    surface_instance: &khr::surface::Instance,
+    surface: Option<vk::SurfaceKHR>,
+) -> Result<(vk::PhysicalDevice, ash::Device, Queues)> {
+    // Prefer a discrete GPU, falling back to integrated, virtual and then software devices.
+    let pdevice = instance
+        .enumerate_physical_devices()
+        .check("vkEnumeratePhysicalDevices")?
+        .into_iter()
+        .min_by_key(
+            |&pdevice| match instance.get_physical_device_properties(pdevice).device_type {
+                vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+                vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+                vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+                vk::PhysicalDeviceType::CPU => 3,
+                _ => 4,
+            },
+        )
+        .ok_or(Error::NoDevice)?;
+    require_extensions(
+        DEVICE_EXTENSIONS,
+        &instance
+            .enumerate_device_extension_properties(pdevice)
+            .check("vkEnumerateDeviceExtensionProperties")?,
+    )?;
+
+    // Find a queue family that is capable of both present and graphics commands.
+    let mut graphics_family = None;
+    for (index, properties) in instance
+        .get_physical_device_queue_family_properties(pdevice)
+        .into_iter()
+        .enumerate()
+    {
+        let graphics = properties.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+        let present = match surface {
+            Some(surface) => surface_instance
+                .get_physical_device_surface_support(pdevice, index as u32, surface)
+                .check("vkGetPhysicalDeviceSurfaceSupportKHR")?,
+            None => true,
+        };
+        if graphics && present {
+            graphics_family = Some(index as u32);
+            break;
+        }
+    }
+    let graphics_family = graphics_family.ok_or(Error::NoQueue)?;
+
+    // Prefer a transfer-only queue family for uploads, falling back to the graphics queue.
+    let transfer_family = instance
+        .get_physical_device_queue_family_properties(pdevice)
+        .into_iter()
+        .enumerate()
+        .find_map(|(index, properties)| {
+            let graphics = properties.queue_flags.contains(vk::QueueFlags::GRAPHICS);
+            let compute = properties.queue_flags.contains(vk::QueueFlags::COMPUTE);
+            let transfer = properties.queue_flags.contains(vk::QueueFlags::TRANSFER);
+            (!graphics && !compute && transfer).then_some(index as u32)
+        })
+        .unwrap_or(graphics_family);
+
+    let device = {
+        // Optional, for wireframe pipelines.
+        let features = vk::PhysicalDeviceFeatures::default().fill_mode_non_solid(
+            instance
+                .get_physical_device_features(pdevice)
+                .fill_mode_non_solid
+                == vk::TRUE,
+        );
+        let extensions = DEVICE_EXTENSIONS.map(|x: &CStr| x.as_ptr());
+
+        let mut descriptor_indexing = vk::PhysicalDeviceDescriptorIndexingFeatures::default()
+            .descriptor_binding_uniform_buffer_update_after_bind(true)
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true);
+
+        let mut dynamic_rendering =
+            vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
+
+        let mut timeline_semaphore =
+            vk::PhysicalDeviceTimelineSemaphoreFeatures::default().timeline_semaphore(true);
+
+        let mut synchronization2 =
+            vk::PhysicalDeviceSynchronization2Features::default().synchronization2(true);
+
+        let priority = [1.0];
+
+        let queue_cinfo = [graphics_family, transfer_family]
+            .into_iter()
+            .dedup()
+            .map(|family_index| {
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(family_index)
+                    .queue_priorities(&priority)
+            })
+            .collect::<Vec<_>>();
+
+        let device_cinfo = vk::DeviceCreateInfo::default()
+            .push_next(&mut descriptor_indexing)
+            .push_next(&mut dynamic_rendering)
+            .push_next(&mut timeline_semaphore)
+            .push_next(&mut synchronization2)
+            .queue_create_infos(&queue_cinfo)
+            .enabled_extension_names(&extensions)
+            .enabled_features(&features);
+
+        instance
+            .create_device(pdevice, &device_cinfo, None)
+            .check("vkCreateDevice")?
+    };
+
+    // Extract queues.
+    let queues = Queues {
+        graphics_family,
+        transfer_family,
+        graphics: device.get_device_queue(graphics_family, 0),
+        present: device.get_device_queue(graphics_family, 0),
+        transfer: device.get_device_queue(transfer_family, 0),
+    };
+
+    Ok((pdevice, device, queues))
+}
+
+// Destroys what `create` made before handing it to the context.
+unsafe fn destroy_instance(
+    instance: &ash::Instance,
+    messenger: Option<&Messenger>,
+    surface: Option<(&khr::surface::Instance, vk::SurfaceKHR)>,
+) {
+    if let Some((surface_instance, surface)) = surface {
+        surface_instance.destroy_surface(surface, None);
+    }
+    if let Some(messenger) = messenger {
+        messenger.destroy();
+    }
+    instance.destroy_instance(None);
+}
+
+fn require_extensions<'a>(
+    required: impl IntoIterator<Item = &'a CStr>,
+    supported: &[vk::ExtensionProperties],
+) -> Result<()> {
+    for name in required {
+        if !supported
+            .iter()
+            .any(|properties| properties.extension_name_as_c_str() == Ok(name))
+        {
+            return Err(Error::MissingExtension(name.to_owned()));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,209 @@
+use ash::vk;
+use std::ffi::CString;
+use std::fmt;
+#[cfg(feature = "hot-reload")]
+use std::path::PathBuf;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A Vulkan call failed.
+    Vulkan {
+        call: &'static str,
+        result: vk::Result,
+    },
+    /// The device was lost. Everything created from its [`Context`](crate::Context) has to be
+    /// recreated.
+    DeviceLost {
+        call: &'static str,
+    },
+    /// The swapchain no longer matches its surface and has to be recreated.
+    OutOfDate,
+    /// Host or device memory ran out.
+    Allocation {
+        call: &'static str,
+        result: vk::Result,
+    },
+    /// The Vulkan library could not be loaded.
+    Loading(ash::LoadingError),
+    /// The window has no usable display or window handle.
+    Window(winit::raw_window_handle::HandleError),
+    /// No physical device meets our requirements.
+    NoDevice,
+    /// No queue family supports graphics, and presenting to the surface if there is one.
+    NoQueue,
+    MissingExtension(CString),
+    /// An optional device feature something needs, e.g. `fillModeNonSolid` for wireframe.
+    MissingFeature(&'static str),
+    UnsupportedFormat(vk::Format),
+    /// Not enough free staging space for the request, even after reclaiming finished
+    /// transfers.
+    StagingFull {
+        requested: u64,
+        available: u64,
+    },
+    Io(std::io::Error),
+    Obj(tobj::LoadError),
+    Png(png::DecodingError),
+    /// An asset decoded fine but has nothing we can use.
+    EmptyAsset(&'static str),
+    /// A call's arguments don't fit together, e.g. image data that doesn't match its extent.
+    InvalidArgument(&'static str),
+    /// SPIR-V that could not be parsed or uses something reflection doesn't understand.
+    InvalidSpirv {
+        shader: String,
+        message: String,
+    },
+    /// A shader's interface doesn't match the Rust side, e.g. a block and the struct written
+    /// to it.
+    LayoutMismatch {
+        shader: String,
+        message: String,
+    },
+    #[cfg(feature = "hot-reload")]
+    Shader {
+        path: PathBuf,
+        log: String,
+    },
+    #[cfg(feature = "hot-reload")]
+    Watch(notify::Error),
+    /// shaderc could not create what it names, e.g. its compiler.
+    #[cfg(feature = "hot-reload")]
+    ShaderCompiler(&'static str),
+}
+
+impl Error {
+    /// Wraps `result` from `call`, singling out conditions callers can recover from.
+    pub fn vulkan(call: &'static str, result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_DEVICE_LOST => Error::DeviceLost { call },
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Error::OutOfDate,
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY => {
+                Error::Allocation { call, result }
+            }
+            result => Error::Vulkan { call, result },
+        }
+    }
+
+    pub fn is_device_lost(&self) -> bool {
+        matches!(self, Error::DeviceLost { .. })
+    }
+
+    pub fn is_out_of_date(&self) -> bool {
+        matches!(self, Error::OutOfDate)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Vulkan { call, result } => write!(f, "{call} failed: {result}"),
+            Error::DeviceLost { call } => write!(f, "device lost during {call}"),
+            Error::OutOfDate => write!(f, "swapchain out of date"),
+            Error::Allocation { call, result } => write!(f, "{call} ran out of memory: {result}"),
+            Error::Loading(err) => write!(f, "could not load Vulkan: {err}"),
+            Error::Window(err) => write!(f, "unusable window: {err}"),
+            Error::NoDevice => write!(f, "no Vulkan device found"),
+            Error::NoQueue => write!(f, "no queue family supports graphics and present"),
+            Error::MissingExtension(name) => {
+                write!(
+                    f,
+                    "required extension {} is not supported",
+                    name.to_string_lossy()
+                )
+            }
+            Error::MissingFeature(name) => write!(f, "device feature {name} is not supported"),
+            Error::UnsupportedFormat(format) => write!(f, "format {format:?} is not supported"),
+            Error::StagingFull {
+                requested,
+                available,
+            } => write!(
+                f,
+                "staging buffer full: requested {requested} bytes, {available} available"
+            ),
+            Error::Io(err) => err.fmt(f),
+            Error::Obj(err) => write!(f, "invalid OBJ: {err}"),
+            Error::Png(err) => write!(f, "invalid PNG: {err}"),
+            Error::EmptyAsset(what) => write!(f, "asset is empty: {what}"),
+            Error::InvalidArgument(message) => write!(f, "invalid argument: {message}"),
+            Error::InvalidSpirv { shader, message } => {
+                write!(f, "could not reflect {shader}: {message}")
+            }
+            Error::LayoutMismatch { shader, message } => {
+                write!(f, "{shader} does not match its layout: {message}")
+            }
+            #[cfg(feature = "hot-reload")]
+            Error::Shader { path, log } => {
+                write!(f, "could not compile {}:\n{log}", path.display())
+            }
+            #[cfg(feature = "hot-reload")]
+            Error::Watch(err) => write!(f, "could not watch shaders: {err}"),
+            #[cfg(feature = "hot-reload")]
+            Error::ShaderCompiler(what) => write!(f, "could not create shader {what}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Loading(err) => Some(err),
+            Error::Window(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::Obj(err) => Some(err),
+            Error::Png(err) => Some(err),
+            #[cfg(feature = "hot-reload")]
+            Error::Watch(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ash::LoadingError> for Error {
+    fn from(err: ash::LoadingError) -> Self {
+        Error::Loading(err)
+    }
+}
+
+impl From<winit::raw_window_handle::HandleError> for Error {
+    fn from(err: winit::raw_window_handle::HandleError) -> Self {
+        Error::Window(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<tobj::LoadError> for Error {
+    fn from(err: tobj::LoadError) -> Self {
+        Error::Obj(err)
+    }
+}
+
+impl From<png::DecodingError> for Error {
+    fn from(err: png::DecodingError) -> Self {
+        Error::Png(err)
+    }
+}
+
+#[cfg(feature = "hot-reload")]
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Self {
+        Error::Watch(err)
+    }
+}
+
+/// Names the Vulkan call behind a raw result, e.g. `.check("vkCreateSampler")?`.
+pub trait Check<T> {
+    fn check(self, call: &'static str) -> Result<T>;
+}
+
+impl<T> Check<T> for ash::prelude::VkResult<T> {
+    fn check(self, call: &'static str) -> Result<T> {
+        self.map_err(|result| Error::vulkan(call, result))
+    }
+}
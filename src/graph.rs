@@ -0,0 +1,763 @@
+use ash::vk;
+use std::rc::Rc;
+
+use crate::context::{Context, Image, ImageView};
+use crate::error::Result;
+use crate::profiler::GpuTimer;
+
+/// An image declared in a [`RenderGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ImageId(usize);
+
+/// A buffer declared in a [`RenderGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BufferId(usize);
+
+/// A pass added to a [`RenderGraph`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PassId(usize);
+
+/// How a pass uses an image.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    /// Rendered to, or resolved into.
+    ColorAttachment,
+    DepthAttachment,
+    /// Sampled in the given shader stages.
+    Sampled(vk::PipelineStageFlags2),
+    /// Read and written as a storage image in the given shader stages.
+    Storage(vk::PipelineStageFlags2),
+    TransferSrc,
+    TransferDst,
+    /// Handed to the presentation engine. Only for swapchain images.
+    Present,
+}
+
+impl ImageAccess {
+    fn usage(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2, vk::ImageLayout) {
+        match self {
+            ImageAccess::ColorAttachment => (
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            ImageAccess::DepthAttachment => (
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL,
+            ),
+            ImageAccess::Sampled(stages) => (
+                stages,
+                vk::AccessFlags2::SHADER_READ,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            ImageAccess::Storage(stages) => (
+                stages,
+                vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+                vk::ImageLayout::GENERAL,
+            ),
+            ImageAccess::TransferSrc => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ),
+            ImageAccess::TransferDst => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+            // The present semaphore takes care of the rest.
+            ImageAccess::Present => (
+                vk::PipelineStageFlags2::NONE,
+                vk::AccessFlags2::NONE,
+                vk::ImageLayout::PRESENT_SRC_KHR,
+            ),
+        }
+    }
+
+    fn image_usage(self) -> vk::ImageUsageFlags {
+        match self {
+            ImageAccess::ColorAttachment => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            ImageAccess::DepthAttachment => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            ImageAccess::Sampled(_) => vk::ImageUsageFlags::SAMPLED,
+            ImageAccess::Storage(_) => vk::ImageUsageFlags::STORAGE,
+            ImageAccess::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            ImageAccess::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            ImageAccess::Present => vk::ImageUsageFlags::empty(),
+        }
+    }
+}
+
+/// How a pass uses a buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BufferAccess {
+    /// Read as a uniform buffer in the given shader stages.
+    Uniform(vk::PipelineStageFlags2),
+    /// Read as a storage buffer in the given shader stages.
+    StorageRead(vk::PipelineStageFlags2),
+    /// Read and written as a storage buffer in the given shader stages.
+    StorageReadWrite(vk::PipelineStageFlags2),
+    TransferSrc,
+    TransferDst,
+}
+
+impl BufferAccess {
+    fn usage(self) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+        match self {
+            BufferAccess::Uniform(stages) => (stages, vk::AccessFlags2::UNIFORM_READ),
+            BufferAccess::StorageRead(stages) => (stages, vk::AccessFlags2::SHADER_READ),
+            BufferAccess::StorageReadWrite(stages) => (
+                stages,
+                vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+            ),
+            BufferAccess::TransferSrc => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_READ,
+            ),
+            BufferAccess::TransferDst => (
+                vk::PipelineStageFlags2::TRANSFER,
+                vk::AccessFlags2::TRANSFER_WRITE,
+            ),
+        }
+    }
+}
+
+const WRITES: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+/// Where a resource was left before the graph runs, by an earlier frame or by commands outside
+/// the graph.
+#[derive(Copy, Clone, Debug)]
+pub struct ResourceState {
+    /// Stages the first use has to wait for.
+    pub stages: vk::PipelineStageFlags2,
+    /// Writes the first use has to wait for.
+    pub access: vk::AccessFlags2,
+    /// `UNDEFINED` discards an image's contents. Unused for buffers.
+    pub layout: vk::ImageLayout,
+}
+
+impl ResourceState {
+    /// Nothing to wait for and no contents worth keeping.
+    pub const UNDEFINED: Self = Self {
+        stages: vk::PipelineStageFlags2::empty(),
+        access: vk::AccessFlags2::empty(),
+        layout: vk::ImageLayout::UNDEFINED,
+    };
+}
+
+// A resource's last write (or layout transition) and the stages that have waited for it since.
+#[derive(Copy, Clone, Debug)]
+struct Tracked {
+    layout: vk::ImageLayout,
+    write_stages: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    read_stages: vk::PipelineStageFlags2,
+}
+
+// What a use has to wait for.
+struct Dependency {
+    src_stages: vk::PipelineStageFlags2,
+    src_access: vk::AccessFlags2,
+    old_layout: vk::ImageLayout,
+}
+
+impl Tracked {
+    fn new(state: ResourceState) -> Self {
+        Self {
+            layout: state.layout,
+            write_stages: state.stages,
+            write_access: state.access,
+            read_stages: vk::PipelineStageFlags2::empty(),
+        }
+    }
+
+    // Records a use, returning the dependency it needs on earlier ones, if any.
+    fn use_as(
+        &mut self,
+        stages: vk::PipelineStageFlags2,
+        access: vk::AccessFlags2,
+        layout: vk::ImageLayout,
+    ) -> Option<Dependency> {
+        let old_layout = self.layout;
+        let writes = access.intersects(WRITES);
+        if writes || layout != old_layout {
+            // Writes and layout transitions wait for every earlier use.
+            let dependency = Dependency {
+                src_stages: self.write_stages | self.read_stages,
+                src_access: self.write_access,
+                old_layout,
+            };
+            self.layout = layout;
+            self.write_stages = stages;
+            self.write_access = access & WRITES;
+            self.read_stages = match writes {
+                true => vk::PipelineStageFlags2::empty(),
+                false => stages,
+            };
+            Some(dependency)
+        } else if !self.read_stages.contains(stages) && !self.write_stages.is_empty() {
+            // Reads only wait for the last write, once per stage.
+            self.read_stages |= stages;
+            Some(Dependency {
+                src_stages: self.write_stages,
+                src_access: self.write_access,
+                old_layout,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn state(&self) -> ResourceState {
+        ResourceState {
+            stages: self.write_stages | self.read_stages,
+            access: self.write_access,
+            layout: self.layout,
+        }
+    }
+}
+
+/// Attachment the graph allocates, see [`TransientImages`]. Its contents don't outlive the
+/// frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TransientDesc {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub samples: vk::SampleCountFlags,
+}
+
+enum ImageSource {
+    Imported(vk::Image, vk::ImageView),
+    Transient(TransientDesc),
+}
+
+struct GraphImage {
+    name: &'static str,
+    source: ImageSource,
+    aspect: vk::ImageAspectFlags,
+    state: Tracked,
+    // Left ready for this once every pass ran.
+    final_access: Option<ImageAccess>,
+}
+
+struct GraphBuffer {
+    buffer: vk::Buffer,
+    state: Tracked,
+}
+
+struct Pass {
+    name: &'static str,
+    images: Vec<(ImageId, ImageAccess)>,
+    buffers: Vec<(BufferId, BufferAccess)>,
+}
+
+/// One frame's passes, in submission order, with the images and buffers each reads and writes.
+/// Barriers and layout transitions between them are derived from those accesses, and transient
+/// attachments are allocated to fit them.
+///
+/// Record with [`RenderGraph::execute`], then begin each pass before recording its commands.
+#[derive(Default)]
+pub struct RenderGraph {
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+}
+
+impl RenderGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an image the graph doesn't own, as left by `state`.
+    pub fn import_image(
+        &mut self,
+        name: &'static str,
+        image: vk::Image,
+        view: vk::ImageView,
+        aspect: vk::ImageAspectFlags,
+        state: ResourceState,
+    ) -> ImageId {
+        self.add_image(name, ImageSource::Imported(image, view), aspect, state)
+    }
+
+    /// Adds an attachment allocated from [`TransientImages`] with whatever usage its passes
+    /// need.
+    pub fn transient_image(&mut self, name: &'static str, desc: TransientDesc) -> ImageId {
+        let aspect = match desc.format {
+            vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+                vk::ImageAspectFlags::DEPTH
+            }
+            vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT => {
+                vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+            }
+            _ => vk::ImageAspectFlags::COLOR,
+        };
+        self.add_image(
+            name,
+            ImageSource::Transient(desc),
+            aspect,
+            ResourceState::UNDEFINED,
+        )
+    }
+
+    fn add_image(
+        &mut self,
+        name: &'static str,
+        source: ImageSource,
+        aspect: vk::ImageAspectFlags,
+        state: ResourceState,
+    ) -> ImageId {
+        self.images.push(GraphImage {
+            name,
+            source,
+            aspect,
+            state: Tracked::new(state),
+            final_access: None,
+        });
+        ImageId(self.images.len() - 1)
+    }
+
+    /// Adds a buffer the graph doesn't own, as left by `state`.
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, state: ResourceState) -> BufferId {
+        self.buffers.push(GraphBuffer {
+            buffer,
+            state: Tracked::new(state),
+        });
+        BufferId(self.buffers.len() - 1)
+    }
+
+    /// Leaves `image` ready for `access` after the last pass, e.g. for presenting.
+    pub fn finish_image(&mut self, image: ImageId, access: ImageAccess) {
+        self.images[image.0].final_access = Some(access);
+    }
+
+    pub fn pass(&mut self, name: &'static str) -> PassBuilder<'_> {
+        PassBuilder {
+            graph: self,
+            pass: Pass {
+                name,
+                images: Vec::new(),
+                buffers: Vec::new(),
+            },
+        }
+    }
+
+    /// Allocates the transient attachments and starts recording into `command_buffer`, timing
+    /// each pass with `timer` if given.
+    pub unsafe fn execute<'a>(
+        self,
+        device: &'a ash::Device,
+        command_buffer: vk::CommandBuffer,
+        transients: &mut TransientImages,
+        timer: Option<&'a mut GpuTimer>,
+    ) -> Result<Execution<'a>> {
+        let mut taken = Vec::new();
+        let mut resolved = Vec::with_capacity(self.images.len());
+        let mut images = self.images;
+        for (index, image) in images.iter_mut().enumerate() {
+            resolved.push(match image.source {
+                ImageSource::Imported(image, view) => (image, view, None),
+                ImageSource::Transient(desc) => {
+                    let usage = self
+                        .passes
+                        .iter()
+                        .flat_map(|pass| &pass.images)
+                        .filter(|(id, _)| id.0 == index)
+                        .fold(vk::ImageUsageFlags::empty(), |usage, (_, access)| {
+                            usage | access.image_usage()
+                        });
+                    let slot =
+                        transients.acquire(&mut taken, image.name, desc, usage, image.aspect)?;
+                    // Contents are discarded, but the last frame's use must be done with.
+                    image.state = Tracked::new(transients.images[slot].state);
+                    (
+                        *transients.images[slot].image,
+                        *transients.images[slot].view,
+                        Some(slot),
+                    )
+                }
+            });
+        }
+
+        Ok(Execution {
+            device,
+            command_buffer,
+            images,
+            buffers: self.buffers,
+            passes: self.passes,
+            resolved,
+            next: 0,
+            timer,
+        })
+    }
+}
+
+pub struct PassBuilder<'g> {
+    graph: &'g mut RenderGraph,
+    pass: Pass,
+}
+
+impl PassBuilder<'_> {
+    pub fn image(mut self, image: ImageId, access: ImageAccess) -> Self {
+        self.pass.images.push((image, access));
+        self
+    }
+
+    pub fn buffer(mut self, buffer: BufferId, access: BufferAccess) -> Self {
+        self.pass.buffers.push((buffer, access));
+        self
+    }
+
+    pub fn add(self) -> PassId {
+        self.graph.passes.push(self.pass);
+        PassId(self.graph.passes.len() - 1)
+    }
+}
+
+/// A [`RenderGraph`] being recorded.
+pub struct Execution<'a> {
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+    images: Vec<GraphImage>,
+    buffers: Vec<GraphBuffer>,
+    passes: Vec<Pass>,
+    // Image, view and transient slot of each image.
+    resolved: Vec<(vk::Image, vk::ImageView, Option<usize>)>,
+    next: usize,
+    // Timestamps each pass as it begins.
+    timer: Option<&'a mut GpuTimer>,
+}
+
+impl Execution<'_> {
+    pub fn image(&self, image: ImageId) -> vk::Image {
+        self.resolved[image.0].0
+    }
+
+    pub fn view(&self, image: ImageId) -> vk::ImageView {
+        self.resolved[image.0].1
+    }
+
+    /// Records the barriers `pass` needs. Passes are begun in the order they were added, and
+    /// none may be skipped.
+    pub unsafe fn begin_pass(&mut self, pass: PassId) {
+        assert_eq!(
+            pass.0, self.next,
+            "pass {} begun out of order",
+            self.passes[pass.0].name
+        );
+        self.next += 1;
+        if let Some(timer) = &mut self.timer {
+            timer.begin_pass(self.command_buffer, self.passes[pass.0].name);
+        }
+        let uses = std::mem::take(&mut self.passes[pass.0].images);
+        let buffer_uses = std::mem::take(&mut self.passes[pass.0].buffers);
+        self.barrier(&uses, &buffer_uses);
+    }
+
+    /// Applies the final accesses and keeps how transient images were left for next frame.
+    pub unsafe fn finish(mut self, transients: &mut TransientImages) {
+        assert_eq!(self.next, self.passes.len(), "not every pass was begun");
+        let finals = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, image)| Some((ImageId(index), image.final_access?)))
+            .collect::<Vec<_>>();
+        self.barrier(&finals, &[]);
+        if let Some(timer) = &mut self.timer {
+            timer.end_frame(self.command_buffer);
+        }
+        for (image, (_, _, slot)) in self.images.iter().zip(&self.resolved) {
+            if let Some(slot) = slot {
+                transients.images[*slot].state = ResourceState {
+                    layout: vk::ImageLayout::UNDEFINED,
+                    ..image.state.state()
+                };
+            }
+        }
+    }
+
+    unsafe fn barrier(
+        &mut self,
+        images: &[(ImageId, ImageAccess)],
+        buffers: &[(BufferId, BufferAccess)],
+    ) {
+        let mut image_barriers = Vec::new();
+        let mut buffer_barriers = Vec::new();
+
+        for &(id, access) in images {
+            let (stages, access_mask, layout) = access.usage();
+            let image = &mut self.images[id.0];
+            if let Some(dependency) = image.state.use_as(stages, access_mask, layout) {
+                image_barriers.push(
+                    vk::ImageMemoryBarrier2::default()
+                        .image(self.resolved[id.0].0)
+                        .subresource_range(
+                            vk::ImageSubresourceRange::default()
+                                .aspect_mask(image.aspect)
+                                .base_mip_level(0)
+                                .level_count(vk::REMAINING_MIP_LEVELS)
+                                .base_array_layer(0)
+                                .layer_count(vk::REMAINING_ARRAY_LAYERS),
+                        )
+                        .src_stage_mask(dependency.src_stages)
+                        .src_access_mask(dependency.src_access)
+                        .dst_stage_mask(stages)
+                        .dst_access_mask(access_mask)
+                        .old_layout(dependency.old_layout)
+                        .new_layout(layout),
+                );
+            }
+        }
+
+        for &(id, access) in buffers {
+            let (stages, access_mask) = access.usage();
+            let buffer = &mut self.buffers[id.0];
+            if let Some(dependency) =
+                buffer
+                    .state
+                    .use_as(stages, access_mask, vk::ImageLayout::UNDEFINED)
+            {
+                buffer_barriers.push(
+                    vk::BufferMemoryBarrier2::default()
+                        .buffer(buffer.buffer)
+                        .offset(0)
+                        .size(vk::WHOLE_SIZE)
+                        .src_stage_mask(dependency.src_stages)
+                        .src_access_mask(dependency.src_access)
+                        .dst_stage_mask(stages)
+                        .dst_access_mask(access_mask),
+                );
+            }
+        }
+
+        if image_barriers.is_empty() && buffer_barriers.is_empty() {
+            return;
+        }
+        self.device.cmd_pipeline_barrier2(
+            self.command_buffer,
+            &vk::DependencyInfo::default()
+                .buffer_memory_barriers(&buffer_barriers)
+                .image_memory_barriers(&image_barriers),
+        );
+    }
+}
+
+/// Images backing the graph's transient attachments, kept from frame to frame.
+pub struct TransientImages {
+    ctx: Rc<Context>,
+    images: Vec<TransientImage>,
+}
+
+struct TransientImage {
+    desc: TransientDesc,
+    usage: vk::ImageUsageFlags,
+    // Declared first so it is dropped before the image.
+    view: ImageView,
+    image: Image,
+    // How the last frame left it.
+    state: ResourceState,
+}
+
+impl TransientImages {
+    pub fn new(ctx: &Rc<Context>) -> Self {
+        Self {
+            ctx: ctx.clone(),
+            images: Vec::new(),
+        }
+    }
+
+    /// Frees every image, e.g. after a resize. None may still be in use.
+    pub fn clear(&mut self) {
+        self.images.clear();
+    }
+
+    // Finds an image not yet `taken` this frame that fits, or creates one.
+    unsafe fn acquire(
+        &mut self,
+        taken: &mut Vec<usize>,
+        name: &str,
+        desc: TransientDesc,
+        usage: vk::ImageUsageFlags,
+        aspect: vk::ImageAspectFlags,
+    ) -> Result<usize> {
+        // Attachments nothing else reads can live in lazily allocated memory.
+        let attachments =
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT;
+        let usage = match attachments.contains(usage) {
+            true => usage | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            false => usage,
+        };
+
+        let slot = self.images.iter().enumerate().position(|(slot, image)| {
+            image.desc == desc && image.usage == usage && !taken.contains(&slot)
+        });
+        let slot = match slot {
+            Some(slot) => slot,
+            None => {
+                self.images.push(self.create(name, desc, usage, aspect)?);
+                self.images.len() - 1
+            }
+        };
+        taken.push(slot);
+        Ok(slot)
+    }
+
+    unsafe fn create(
+        &self,
+        name: &str,
+        desc: TransientDesc,
+        usage: vk::ImageUsageFlags,
+        aspect: vk::ImageAspectFlags,
+    ) -> Result<TransientImage> {
+        let image = Image::new(
+            &self.ctx,
+            &vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(
+                    vk::Extent3D::default()
+                        .width(desc.extent.width)
+                        .height(desc.extent.height)
+                        .depth(1),
+                )
+                .mip_levels(1)
+                .array_layers(1)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .samples(desc.samples)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .format(desc.format)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(usage),
+            &vk_mem::AllocationCreateInfo {
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        )?;
+        self.ctx.set_name(*image, name)?;
+
+        let view = image.create_view(
+            vk::ImageViewType::TYPE_2D,
+            desc.format,
+            vk::ImageSubresourceRange {
+                aspect_mask: aspect,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        )?;
+
+        Ok(TransientImage {
+            desc,
+            usage,
+            view,
+            image,
+            state: ResourceState::UNDEFINED,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn use_image(tracked: &mut Tracked, access: ImageAccess) -> Option<Dependency> {
+        let (stages, access, layout) = access.usage();
+        tracked.use_as(stages, access, layout)
+    }
+
+    #[test]
+    fn first_use_from_undefined() {
+        let mut tracked = Tracked::new(ResourceState::UNDEFINED);
+        let dependency = use_image(&mut tracked, ImageAccess::ColorAttachment).unwrap();
+        assert_eq!(dependency.src_stages, vk::PipelineStageFlags2::empty());
+        assert_eq!(dependency.src_access, vk::AccessFlags2::empty());
+        assert_eq!(dependency.old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(tracked.layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+
+    #[test]
+    fn read_after_write() {
+        let mut tracked = Tracked::new(ResourceState::UNDEFINED);
+        let stages = vk::PipelineStageFlags2::COMPUTE_SHADER;
+        tracked.use_as(
+            stages,
+            vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+            vk::ImageLayout::GENERAL,
+        );
+        let dependency = tracked
+            .use_as(
+                vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                vk::AccessFlags2::SHADER_READ,
+                vk::ImageLayout::GENERAL,
+            )
+            .unwrap();
+        assert_eq!(dependency.src_stages, stages);
+        assert_eq!(dependency.src_access, vk::AccessFlags2::SHADER_WRITE);
+        assert_eq!(dependency.old_layout, vk::ImageLayout::GENERAL);
+    }
+
+    #[test]
+    fn read_after_read() {
+        let mut tracked = Tracked::new(ResourceState::UNDEFINED);
+        use_image(&mut tracked, ImageAccess::TransferDst);
+        let sampled = ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER);
+        assert!(use_image(&mut tracked, sampled).is_some());
+        assert!(use_image(&mut tracked, sampled).is_none());
+
+        // Nor does reading something nothing has written.
+        let mut tracked = Tracked::new(ResourceState {
+            layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ..ResourceState::UNDEFINED
+        });
+        assert!(use_image(&mut tracked, sampled).is_none());
+    }
+
+    #[test]
+    fn layout_change() {
+        let mut tracked = Tracked::new(ResourceState::UNDEFINED);
+        use_image(&mut tracked, ImageAccess::ColorAttachment);
+        let dependency = use_image(
+            &mut tracked,
+            ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+        )
+        .unwrap();
+        assert_eq!(
+            dependency.src_stages,
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            dependency.src_access,
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(
+            dependency.old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(tracked.layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        // Writing after the read only waits for it, as the transition already waited for the
+        // attachment write.
+        let dependency = use_image(&mut tracked, ImageAccess::TransferDst).unwrap();
+        assert_eq!(
+            dependency.src_stages,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(
+            dependency.old_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(tracked.layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+    }
+}
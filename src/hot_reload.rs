@@ -0,0 +1,87 @@
+use notify::{RecursiveMode, Watcher};
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+
+use crate::error::{Error, Result};
+
+/// Watches a shader directory and recompiles vertex and fragment shaders as they are saved.
+pub struct ShaderWatcher {
+    compiler: shaderc::Compiler,
+    options: shaderc::CompileOptions<'static>,
+    events: Receiver<notify::Result<notify::Event>>,
+    _watcher: notify::RecommendedWatcher,
+}
+
+impl ShaderWatcher {
+    pub fn new(dir: &Path) -> Result<Self> {
+        let (sender, events) = std::sync::mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+
+        let compiler = shaderc::Compiler::new().ok_or(Error::ShaderCompiler("compiler"))?;
+        let mut options =
+            shaderc::CompileOptions::new().ok_or(Error::ShaderCompiler("compile options"))?;
+        options.set_target_env(
+            shaderc::TargetEnv::Vulkan,
+            shaderc::EnvVersion::Vulkan1_2 as u32,
+        );
+
+        Ok(Self {
+            compiler,
+            options,
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    /// Compiles the shaders changed since the last poll. Returns each one's file name, e.g.
+    /// `shader.frag`, with its SPIR-V or why it failed to compile.
+    pub fn poll(&mut self) -> Vec<(String, Result<Vec<u8>>)> {
+        // Editors often write a file several times per save, so compile each once.
+        let mut changed = BTreeSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if event.kind.is_create() || event.kind.is_modify() => {
+                    changed.extend(event.paths)
+                }
+                Ok(_) => {}
+                Err(err) => println!("Shader watcher: {err}"),
+            }
+        }
+
+        changed
+            .into_iter()
+            .filter_map(|path| {
+                let kind = match path.extension()?.to_str()? {
+                    "vert" => shaderc::ShaderKind::Vertex,
+                    "frag" => shaderc::ShaderKind::Fragment,
+                    _ => return None,
+                };
+                let name = path.file_name()?.to_str()?.to_owned();
+                Some((name, self.compile(&path, kind)))
+            })
+            .collect()
+    }
+
+    fn compile(&mut self, path: &Path, kind: shaderc::ShaderKind) -> Result<Vec<u8>> {
+        let source = std::fs::read_to_string(path)?;
+        let artifact = self
+            .compiler
+            .compile_into_spirv(
+                &source,
+                kind,
+                &path.to_string_lossy(),
+                "main",
+                Some(&self.options),
+            )
+            .map_err(|err| Error::Shader {
+                path: path.to_owned(),
+                log: err.to_string(),
+            })?;
+        if artifact.get_num_warnings() > 0 {
+            println!("{}", artifact.get_warning_messages());
+        }
+        Ok(artifact.as_binary_u8().to_vec())
+    }
+}
@@ -0,0 +1,29 @@
+//! Vulkan renderer shared by the viewer and headless tools.
+
+// Nearly everything here drives Vulkan directly; the unsafe contract is the Vulkan spec.
+#![allow(clippy::missing_safety_doc)]
+
+pub mod camera_path;
+pub mod capture;
+pub mod context;
+pub mod debug;
+pub mod device;
+pub mod error;
+pub mod graph;
+#[cfg(feature = "hot-reload")]
+pub mod hot_reload;
+pub mod pipeline;
+pub mod pipeline_cache;
+pub mod profiler;
+pub mod reflect;
+pub mod renderer;
+pub mod staging;
+pub mod streaming;
+pub mod swapchain;
+pub mod targets;
+pub mod upload;
+
+pub use context::Context;
+pub use error::{Error, Result};
+pub use pipeline::{PipelineDesc, Variant};
+pub use renderer::{Frame, FrameOutput, PostSettings, Renderer, RendererConfig, Tonemap};
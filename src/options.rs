@@ -0,0 +1,121 @@
+use raytrace::debug::{parse_severity, Severity};
+use raytrace::renderer::DEFAULT_FRAMES_IN_FLIGHT;
+use std::path::PathBuf;
+
+use crate::bench::BenchLength;
+
+/// Command line options.
+pub struct Options {
+    /// Render this many frames at a fixed timestep, saving each as a PNG, then exit.
+    pub record_frames: Option<u32>,
+    pub record_dir: PathBuf,
+    /// Save the live camera path to this file on exit.
+    pub camera_record: Option<PathBuf>,
+    /// Drive the camera from this path file instead of live input.
+    pub camera_play: Option<PathBuf>,
+    /// Validation messages of at least this severity are logged; `None` disables validation.
+    /// Set by `--validation` or `RAYTRACE_VALIDATION`, which may name the severity.
+    pub validation: Option<Severity>,
+    /// Frames recorded ahead of the GPU.
+    pub frames_in_flight: usize,
+    /// Save CPU and GPU frame timings to this Chrome trace file on exit.
+    pub trace: Option<PathBuf>,
+    /// Time this many frames, or for this long, once the scene has loaded, then save the
+    /// results to `bench_out` and exit. The camera follows `--camera-play` if given, resting at
+    /// the end of the path once it runs out, and otherwise stays put while the model turns.
+    pub bench: Option<BenchLength>,
+    pub bench_out: PathBuf,
+    /// Load the scene's OBJ mesh and PNG texture from these files instead of the built-in
+    /// viking room.
+    pub mesh: Option<PathBuf>,
+    pub texture: Option<PathBuf>,
+    /// Render offscreen without a window, e.g. on a software device in CI.
+    pub headless: bool,
+}
+
+impl Options {
+    pub fn from_args() -> Self {
+        let mut options = Self {
+            record_frames: None,
+            record_dir: PathBuf::from("recording"),
+            camera_record: None,
+            camera_play: None,
+            validation: std::env::var("RAYTRACE_VALIDATION")
+                .ok()
+                .map(|level| parse_severity(&level).unwrap_or(Severity::WARNING)),
+            frames_in_flight: DEFAULT_FRAMES_IN_FLIGHT,
+            trace: None,
+            bench: None,
+            bench_out: PathBuf::from("bench.json"),
+            mesh: None,
+            texture: None,
+            headless: false,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => {
+                    let frames = args
+                        .next()
+                        .and_then(|frames| frames.parse().ok())
+                        .expect("--record expects a frame count.");
+                    options.record_frames = Some(frames);
+                }
+                "--record-dir" => {
+                    options.record_dir = args.next().expect("--record-dir expects a path.").into();
+                }
+                "--camera-record" => {
+                    options.camera_record =
+                        Some(args.next().expect("--camera-record expects a path.").into());
+                }
+                "--camera-play" => {
+                    options.camera_play =
+                        Some(args.next().expect("--camera-play expects a path.").into());
+                }
+                "--frames-in-flight" => {
+                    options.frames_in_flight = args
+                        .next()
+                        .and_then(|frames| frames.parse().ok())
+                        .filter(|&frames| frames > 0)
+                        .expect("--frames-in-flight expects a positive count.");
+                }
+                "--trace" => {
+                    options.trace = Some(args.next().expect("--trace expects a path.").into());
+                }
+                "--bench" => {
+                    options.bench = Some(
+                        args.next()
+                            .and_then(|length| BenchLength::parse(&length))
+                            .expect("--bench expects a frame count or seconds, e.g. 10s."),
+                    );
+                }
+                "--bench-out" => {
+                    options.bench_out = args.next().expect("--bench-out expects a path.").into();
+                }
+                "--mesh" => {
+                    options.mesh = Some(args.next().expect("--mesh expects a path.").into());
+                }
+                "--texture" => {
+                    options.texture = Some(args.next().expect("--texture expects a path.").into());
+                }
+                "--headless" => options.headless = true,
+                "--validation" => {
+                    options.validation = options.validation.or(Some(Severity::WARNING));
+                }
+                _ => panic!("Unknown argument {arg:?}."),
+            }
+        }
+
+        // Nothing else ends a headless run.
+        if options.headless
+            && options.bench.is_none()
+            && options.record_frames.is_none()
+            && options.camera_play.is_none()
+        {
+            panic!("--headless needs --bench, --record or --camera-play.");
+        }
+
+        options
+    }
+}
@@ -0,0 +1,396 @@
+use ash::vk;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::context::{Context, Pipeline};
+use crate::error::{Error, Result};
+use crate::reflect::Reflection;
+
+/// Fixed-function state of a graphics pipeline. Pipelines are built on demand for each distinct
+/// description they are drawn with, see [`GraphicsPipelines`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineDesc {
+    pub topology: vk::PrimitiveTopology,
+    /// Anything but `FILL` needs the `fillModeNonSolid` feature.
+    pub polygon_mode: vk::PolygonMode,
+    pub cull_mode: vk::CullModeFlags,
+    pub front_face: vk::FrontFace,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: vk::CompareOp,
+    pub blend: Blend,
+    /// Off for depth-only passes.
+    pub color_write: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Blend {
+    Opaque,
+    /// Blends by source alpha over what is already there.
+    Alpha,
+}
+
+impl Default for PipelineDesc {
+    /// Opaque, back-face culled triangles with depth testing.
+    fn default() -> Self {
+        Self {
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            blend: Blend::Opaque,
+            color_write: true,
+        }
+    }
+}
+
+impl PipelineDesc {
+    // Resets the state set through extended dynamic state, so descriptions that only differ
+    // there share a pipeline.
+    fn without_dynamic_state(self) -> Self {
+        let default = Self::default();
+        Self {
+            cull_mode: default.cull_mode,
+            front_face: default.front_face,
+            depth_test: default.depth_test,
+            depth_write: default.depth_write,
+            depth_compare_op: default.depth_compare_op,
+            ..self
+        }
+    }
+}
+
+/// The scene pipeline variants the viewer can switch between.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Opaque,
+    Wireframe,
+    AlphaBlended,
+    DoubleSided,
+    DepthPrepass,
+    Lines,
+}
+
+impl Variant {
+    pub fn next(self) -> Self {
+        match self {
+            Variant::Opaque => Variant::Wireframe,
+            Variant::Wireframe => Variant::AlphaBlended,
+            Variant::AlphaBlended => Variant::DoubleSided,
+            Variant::DoubleSided => Variant::DepthPrepass,
+            Variant::DepthPrepass => Variant::Lines,
+            Variant::Lines => Variant::Opaque,
+        }
+    }
+
+    pub fn desc(self) -> PipelineDesc {
+        let opaque = PipelineDesc::default();
+        match self {
+            Variant::Opaque => opaque,
+            Variant::Wireframe => PipelineDesc {
+                polygon_mode: vk::PolygonMode::LINE,
+                cull_mode: vk::CullModeFlags::NONE,
+                ..opaque
+            },
+            // Blended surfaces are drawn after the opaque ones and must not hide each other.
+            Variant::AlphaBlended => PipelineDesc {
+                blend: Blend::Alpha,
+                depth_write: false,
+                ..opaque
+            },
+            Variant::DoubleSided => PipelineDesc {
+                cull_mode: vk::CullModeFlags::NONE,
+                ..opaque
+            },
+            // Writes depth with the usual `LESS` test but no color; nothing draws after it, so
+            // only the clear color shows.
+            Variant::DepthPrepass => PipelineDesc {
+                color_write: false,
+                ..opaque
+            },
+            Variant::Lines => PipelineDesc {
+                topology: vk::PrimitiveTopology::LINE_LIST,
+                cull_mode: vk::CullModeFlags::NONE,
+                ..opaque
+            },
+        }
+    }
+}
+
+/// Attachments pipelines render to, as passed to `vkCmdBeginRendering`.
+#[derive(Copy, Clone, Debug)]
+pub struct Targets {
+    pub color_format: vk::Format,
+    pub depth_format: Option<vk::Format>,
+    pub samples: vk::SampleCountFlags,
+}
+
+/// Graphics pipelines sharing shaders, layout and targets, one per [`PipelineDesc`] drawn with.
+/// Viewport and scissor are always dynamic, and so are culling and depth state when the device
+/// supports extended dynamic state.
+pub struct GraphicsPipelines {
+    ctx: Rc<Context>,
+    name: &'static str,
+    cache: vk::PipelineCache,
+    layout: vk::PipelineLayout,
+    vert_shader: vk::ShaderModule,
+    frag_shader: vk::ShaderModule,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    targets: Targets,
+    extended_dynamic_state: bool,
+    fill_mode_non_solid: bool,
+    pipelines: HashMap<PipelineDesc, Pipeline>,
+}
+
+impl GraphicsPipelines {
+    /// The shader modules must outlive the pipelines. Vertex input is read from the vertex
+    /// shader's reflection, see [`Reflection::vertex_input`].
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        name: &'static str,
+        cache: vk::PipelineCache,
+        layout: vk::PipelineLayout,
+        (vert_shader, vert_reflection): (vk::ShaderModule, &Reflection),
+        frag_shader: vk::ShaderModule,
+        targets: Targets,
+    ) -> Self {
+        let (vertex_bindings, vertex_attributes) = vert_reflection.vertex_input();
+        // Extended dynamic state is core since Vulkan 1.3.
+        let properties = ctx.instance.get_physical_device_properties(ctx.pdevice);
+        let features = ctx.instance.get_physical_device_features(ctx.pdevice);
+        Self {
+            ctx: ctx.clone(),
+            name,
+            cache,
+            layout,
+            vert_shader,
+            frag_shader,
+            vertex_bindings,
+            vertex_attributes,
+            targets,
+            extended_dynamic_state: properties.api_version >= vk::API_VERSION_1_3,
+            fill_mode_non_solid: features.fill_mode_non_solid == vk::TRUE,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    /// Whether the device can draw with `desc`.
+    pub fn supports(&self, desc: &PipelineDesc) -> bool {
+        desc.polygon_mode == vk::PolygonMode::FILL || self.fill_mode_non_solid
+    }
+
+    /// Builds the pipeline for `desc` unless an equivalent one exists.
+    pub unsafe fn get(&mut self, desc: &PipelineDesc) -> Result<vk::Pipeline> {
+        let key = match self.extended_dynamic_state {
+            true => desc.without_dynamic_state(),
+            false => *desc,
+        };
+        if let Some(pipeline) = self.pipelines.get(&key) {
+            return Ok(**pipeline);
+        }
+        let pipeline = self.build(&key)?;
+        Ok(**self.pipelines.entry(key).or_insert(pipeline))
+    }
+
+    /// Binds the pipeline for `desc`, building it if needed, and sets its dynamic state.
+    pub unsafe fn bind(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        desc: &PipelineDesc,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let pipeline = self.get(desc)?;
+        let device = &self.ctx.device;
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+        device.cmd_set_viewport(
+            command_buffer,
+            0,
+            &[vk::Viewport {
+                x: 0.,
+                y: 0.,
+                width: extent.width as f32,
+                height: extent.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+        );
+        device.cmd_set_scissor(
+            command_buffer,
+            0,
+            &[vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent,
+            }],
+        );
+        if self.extended_dynamic_state {
+            device.cmd_set_cull_mode(command_buffer, desc.cull_mode);
+            device.cmd_set_front_face(command_buffer, desc.front_face);
+            device.cmd_set_depth_test_enable(command_buffer, desc.depth_test);
+            device.cmd_set_depth_write_enable(command_buffer, desc.depth_write);
+            device.cmd_set_depth_compare_op(command_buffer, desc.depth_compare_op);
+        }
+        Ok(())
+    }
+
+    /// Drops every pipeline, for new targets. None may still be in use.
+    pub fn set_targets(&mut self, targets: Targets) {
+        self.pipelines.clear();
+        self.targets = targets;
+    }
+
+    /// The same pipelines with a new vertex shader. Every description used so far is rebuilt
+    /// up front, so a broken shader fails here rather than mid-frame.
+    pub unsafe fn with_vert_shader(
+        &self,
+        shader: vk::ShaderModule,
+        reflection: &Reflection,
+    ) -> Result<Self> {
+        let (vertex_bindings, vertex_attributes) = reflection.vertex_input();
+        self.rebuilt(Self {
+            vert_shader: shader,
+            vertex_bindings,
+            vertex_attributes,
+            ..self.settings()
+        })
+    }
+
+    /// The same pipelines with a new fragment shader, see [`Self::with_vert_shader`].
+    pub unsafe fn with_frag_shader(&self, shader: vk::ShaderModule) -> Result<Self> {
+        self.rebuilt(Self {
+            frag_shader: shader,
+            ..self.settings()
+        })
+    }
+
+    // Everything but the pipelines.
+    fn settings(&self) -> Self {
+        Self {
+            ctx: self.ctx.clone(),
+            name: self.name,
+            cache: self.cache,
+            layout: self.layout,
+            vert_shader: self.vert_shader,
+            frag_shader: self.frag_shader,
+            vertex_bindings: self.vertex_bindings.clone(),
+            vertex_attributes: self.vertex_attributes.clone(),
+            targets: self.targets,
+            extended_dynamic_state: self.extended_dynamic_state,
+            fill_mode_non_solid: self.fill_mode_non_solid,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    unsafe fn rebuilt(&self, mut pipelines: Self) -> Result<Self> {
+        for desc in self.pipelines.keys() {
+            pipelines.get(desc)?;
+        }
+        Ok(pipelines)
+    }
+
+    unsafe fn build(&self, desc: &PipelineDesc) -> Result<Pipeline> {
+        if !self.supports(desc) {
+            return Err(Error::MissingFeature("fillModeNonSolid"));
+        }
+
+        let mut dynamic_states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if self.extended_dynamic_state {
+            dynamic_states.extend([
+                vk::DynamicState::CULL_MODE,
+                vk::DynamicState::FRONT_FACE,
+                vk::DynamicState::DEPTH_TEST_ENABLE,
+                vk::DynamicState::DEPTH_WRITE_ENABLE,
+                vk::DynamicState::DEPTH_COMPARE_OP,
+            ]);
+        }
+
+        let blend = match desc.blend {
+            Blend::Opaque => vk::PipelineColorBlendAttachmentState::default().blend_enable(false),
+            Blend::Alpha => vk::PipelineColorBlendAttachmentState::default()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_blend_op(vk::BlendOp::ADD)
+                .src_alpha_blend_factor(vk::BlendFactor::ONE)
+                .dst_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .alpha_blend_op(vk::BlendOp::ADD),
+        }
+        .color_write_mask(match desc.color_write {
+            true => vk::ColorComponentFlags::RGBA,
+            false => vk::ColorComponentFlags::empty(),
+        });
+
+        let color_formats = [self.targets.color_format];
+        let mut rendering =
+            vk::PipelineRenderingCreateInfo::default().color_attachment_formats(&color_formats);
+        if let Some(depth_format) = self.targets.depth_format {
+            rendering = rendering.depth_attachment_format(depth_format);
+        }
+
+        let pipeline = self.ctx.create_graphics_pipeline(
+            self.cache,
+            &vk::GraphicsPipelineCreateInfo::default()
+                .push_next(&mut rendering)
+                .stages(&[
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .module(self.vert_shader)
+                        .stage(vk::ShaderStageFlags::VERTEX)
+                        .name(c"main"),
+                    vk::PipelineShaderStageCreateInfo::default()
+                        .module(self.frag_shader)
+                        .stage(vk::ShaderStageFlags::FRAGMENT)
+                        .name(c"main"),
+                ])
+                .vertex_input_state(
+                    &vk::PipelineVertexInputStateCreateInfo::default()
+                        .vertex_binding_descriptions(&self.vertex_bindings)
+                        .vertex_attribute_descriptions(&self.vertex_attributes),
+                )
+                .input_assembly_state(
+                    &vk::PipelineInputAssemblyStateCreateInfo::default()
+                        .topology(desc.topology)
+                        .primitive_restart_enable(false),
+                )
+                .viewport_state(
+                    &vk::PipelineViewportStateCreateInfo::default()
+                        .viewport_count(1)
+                        .scissor_count(1),
+                )
+                .rasterization_state(
+                    &vk::PipelineRasterizationStateCreateInfo::default()
+                        .depth_clamp_enable(false)
+                        .rasterizer_discard_enable(false)
+                        .polygon_mode(desc.polygon_mode)
+                        .line_width(1.0)
+                        .cull_mode(desc.cull_mode)
+                        .front_face(desc.front_face)
+                        .depth_bias_enable(false),
+                )
+                .multisample_state(
+                    &vk::PipelineMultisampleStateCreateInfo::default()
+                        .sample_shading_enable(false)
+                        .rasterization_samples(self.targets.samples),
+                )
+                .color_blend_state(
+                    &vk::PipelineColorBlendStateCreateInfo::default()
+                        .logic_op_enable(false)
+                        .attachments(&[blend]),
+                )
+                .depth_stencil_state(
+                    &vk::PipelineDepthStencilStateCreateInfo::default()
+                        .depth_test_enable(desc.depth_test)
+                        .depth_write_enable(desc.depth_write)
+                        .depth_compare_op(desc.depth_compare_op),
+                )
+                .dynamic_state(
+                    &vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&dynamic_states),
+                )
+                .layout(self.layout),
+        )?;
+        self.ctx.set_name(*pipeline, self.name)?;
+        Ok(pipeline)
+    }
+}
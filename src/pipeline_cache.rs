@@ -0,0 +1,220 @@
+use ash::vk;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::context::{Context, Owned, PipelineCache};
+use crate::error::{Check, Result};
+
+// Our header goes in front of the driver's data. Drivers check their own header, but it
+// doesn't include the driver version and not every driver rejects stale data gracefully.
+const MAGIC: [u8; 4] = *b"RTPC";
+const HEADER_SIZE: usize = 4 * 5 + vk::UUID_SIZE;
+
+/// Where the viewer keeps its pipeline cache, e.g. `~/.cache/raytrace/pipelines.bin`.
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::cache_dir()?.join("raytrace").join("pipelines.bin"))
+}
+
+/// Creates a pipeline cache seeded from `path`. A missing, corrupt or stale file (one written
+/// for another device or driver version) starts an empty cache instead.
+pub unsafe fn load(ctx: &Rc<Context>, path: Option<&Path>) -> Result<PipelineCache> {
+    let data = path.map_or_else(Vec::new, |path| read_valid(ctx, path));
+    let cache = match create(ctx, &data) {
+        Err(err) if !data.is_empty() => {
+            println!("Discarding pipeline cache the driver rejected: {err}");
+            create(ctx, &[])?
+        }
+        cache => cache?,
+    };
+    ctx.set_name(*cache, "pipeline cache")?;
+    Ok(cache)
+}
+
+/// Writes `cache` to `path` for [`load`] to pick up next run.
+pub unsafe fn save(ctx: &Context, cache: vk::PipelineCache, path: &Path) -> Result<()> {
+    let data = ctx
+        .device
+        .get_pipeline_cache_data(cache)
+        .check("vkGetPipelineCacheData")?;
+    let mut bytes = Header::current(ctx).to_bytes(data.len() as u32);
+    bytes.extend(data);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // Written aside and renamed, so an interrupted save can't leave a truncated cache.
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, &bytes)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
+
+unsafe fn create(ctx: &Rc<Context>, data: &[u8]) -> Result<PipelineCache> {
+    let cache = ctx
+        .device
+        .create_pipeline_cache(
+            &vk::PipelineCacheCreateInfo::default().initial_data(data),
+            None,
+        )
+        .check("vkCreatePipelineCache")?;
+    Ok(Owned::new(ctx, cache))
+}
+
+// The driver's data from `path`, or nothing if it can't be used on this device.
+unsafe fn read_valid(ctx: &Context, path: &Path) -> Vec<u8> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Vec::new(),
+        Err(err) => {
+            println!("Could not read pipeline cache {}: {err}", path.display());
+            return Vec::new();
+        }
+    };
+    match Header::parse(&bytes) {
+        Some((header, data)) if header == Header::current(ctx) => data.to_vec(),
+        Some(_) => {
+            println!("Discarding pipeline cache from another device or driver version.");
+            Vec::new()
+        }
+        None => {
+            println!("Discarding corrupt pipeline cache {}.", path.display());
+            Vec::new()
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Header {
+    vendor_id: u32,
+    device_id: u32,
+    driver_version: u32,
+    uuid: [u8; vk::UUID_SIZE],
+}
+
+impl Header {
+    unsafe fn current(ctx: &Context) -> Self {
+        let properties = ctx.instance.get_physical_device_properties(ctx.pdevice);
+        Self {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    fn to_bytes(&self, data_len: u32) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + data_len as usize);
+        bytes.extend(MAGIC);
+        for value in [
+            self.vendor_id,
+            self.device_id,
+            self.driver_version,
+            data_len,
+        ] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(self.uuid);
+        bytes
+    }
+
+    // Splits off the header, checking the data that follows is complete.
+    fn parse(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        let (header, data) = bytes.split_at_checked(HEADER_SIZE)?;
+        let (magic, header) = header.split_first_chunk::<4>()?;
+        let mut values = header
+            .chunks_exact(4)
+            .map(|value| u32::from_le_bytes(value.try_into().unwrap()));
+        let (vendor_id, device_id, driver_version, data_len) = (
+            values.next()?,
+            values.next()?,
+            values.next()?,
+            values.next()?,
+        );
+        if *magic != MAGIC || data.len() != data_len as usize {
+            return None;
+        }
+        Some((
+            Self {
+                vendor_id,
+                device_id,
+                driver_version,
+                uuid: header[16..].try_into().ok()?,
+            },
+            data,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> Header {
+        Header {
+            vendor_id: 0x10de,
+            device_id: 0x2684,
+            driver_version: 0x8a5e_0000,
+            uuid: [7; vk::UUID_SIZE],
+        }
+    }
+
+    fn with_data(header: &Header, data: &[u8]) -> Vec<u8> {
+        let mut bytes = header.to_bytes(data.len() as u32);
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = with_data(&header(), b"driver data");
+        let (parsed, data) = Header::parse(&bytes).unwrap();
+        assert!(parsed == header());
+        assert_eq!(data, b"driver data");
+
+        let bytes = with_data(&header(), &[]);
+        assert_eq!(bytes.len(), HEADER_SIZE);
+        assert!(Header::parse(&bytes).unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn stale() {
+        // Parses, but doesn't match the current device.
+        let stale = [
+            Header {
+                vendor_id: 0x1002,
+                ..header()
+            },
+            Header {
+                device_id: 0x2685,
+                ..header()
+            },
+            Header {
+                driver_version: 0x8a5f_0000,
+                ..header()
+            },
+            Header {
+                uuid: [8; vk::UUID_SIZE],
+                ..header()
+            },
+        ];
+        for stale in stale {
+            let bytes = with_data(&stale, b"driver data");
+            assert!(Header::parse(&bytes).unwrap().0 != header());
+        }
+    }
+
+    #[test]
+    fn corrupt() {
+        let bytes = with_data(&header(), b"driver data");
+
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(Header::parse(&magic).is_none());
+
+        // Truncated data, extra data and a truncated header.
+        assert!(Header::parse(&bytes[..bytes.len() - 1]).is_none());
+        assert!(Header::parse(&[&bytes[..], b"!"].concat()).is_none());
+        assert!(Header::parse(&bytes[..HEADER_SIZE - 1]).is_none());
+        assert!(Header::parse(&[]).is_none());
+    }
+}
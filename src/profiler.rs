@@ -0,0 +1,417 @@
+use ash::vk;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::context::{Context, Owned, QueryPool};
+use crate::error::{Check, Result};
+
+// Timestamps per frame: one as each pass begins, and one once the last has ended.
+const MAX_TIMESTAMPS: u32 = 32;
+
+/// GPU time spent in a pass, in milliseconds since the frame's first pass began.
+#[derive(Copy, Clone, Debug)]
+pub struct PassTime {
+    pub name: &'static str,
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+/// GPU timings of one frame.
+#[derive(Clone, Debug)]
+pub struct GpuFrame {
+    /// When the CPU started recording the frame.
+    pub recorded: Instant,
+    /// The GPU clock as the first pass began, in nanoseconds.
+    pub start_ns: f64,
+    pub passes: Vec<PassTime>,
+}
+
+/// Time between the starts of two consecutive frames on the CPU.
+#[derive(Copy, Clone, Debug)]
+pub struct CpuFrame {
+    pub start: Instant,
+    pub duration: Duration,
+}
+
+/// Timestamp queries written as each pass of a frame begins. A frame's results are read back
+/// when its resources come around again, frames-in-flight frames later.
+pub struct GpuTimer {
+    // One pool per frame in flight.
+    pools: Vec<QueryPool>,
+    // Passes each pool holds timestamps for, once its frame was fully recorded.
+    passes: Vec<Vec<&'static str>>,
+    recorded: Vec<Instant>,
+    // The frame being recorded and the passes it has begun.
+    frame: usize,
+    current: Vec<&'static str>,
+    // Nanoseconds per tick.
+    period: f64,
+    // Timestamps wrap at this many bits.
+    mask: u64,
+    ctx: Rc<Context>,
+}
+
+impl GpuTimer {
+    /// `None` if the graphics queue can't write timestamps.
+    pub unsafe fn new(ctx: &Rc<Context>, frames: usize) -> Result<Option<Self>> {
+        let valid_bits = ctx
+            .instance
+            .get_physical_device_queue_family_properties(ctx.pdevice)
+            [ctx.queues.graphics_family as usize]
+            .timestamp_valid_bits;
+        if valid_bits == 0 {
+            println!("Timestamps are not supported on the graphics queue; GPU timings disabled.");
+            return Ok(None);
+        }
+        let period = ctx
+            .instance
+            .get_physical_device_properties(ctx.pdevice)
+            .limits
+            .timestamp_period;
+
+        let pools = (0..frames)
+            .map(|frame| {
+                let pool = ctx
+                    .device
+                    .create_query_pool(
+                        &vk::QueryPoolCreateInfo::default()
+                            .query_type(vk::QueryType::TIMESTAMP)
+                            .query_count(MAX_TIMESTAMPS),
+                        None,
+                    )
+                    .check("vkCreateQueryPool")?;
+                let pool = Owned::new(ctx, pool);
+                ctx.set_name(*pool, &format!("timestamps {frame}"))?;
+                Ok(pool)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Some(Self {
+            pools,
+            passes: vec![Vec::new(); frames],
+            recorded: vec![Instant::now(); frames],
+            frame: 0,
+            current: Vec::new(),
+            period: period as f64,
+            mask: match valid_bits {
+                64 => u64::MAX,
+                bits => (1 << bits) - 1,
+            },
+            ctx: ctx.clone(),
+        }))
+    }
+
+    /// Starts recording `frame`'s timestamps into `command_buffer`, and returns the timings
+    /// `frame` last measured. Its last submission must have finished.
+    pub unsafe fn begin_frame(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> Result<Option<GpuFrame>> {
+        let timings = self.read(frame)?;
+        self.frame = frame;
+        self.current.clear();
+        self.recorded[frame] = Instant::now();
+        self.ctx
+            .device
+            .cmd_reset_query_pool(command_buffer, *self.pools[frame], 0, MAX_TIMESTAMPS);
+        Ok(timings)
+    }
+
+    /// Writes the timestamp `pass` begins at, once earlier commands are done. Passes beyond
+    /// the pool's capacity aren't timed.
+    pub unsafe fn begin_pass(&mut self, command_buffer: vk::CommandBuffer, pass: &'static str) {
+        if self.current.len() + 1 < MAX_TIMESTAMPS as usize {
+            self.write(command_buffer);
+            self.current.push(pass);
+        }
+    }
+
+    /// Writes the timestamp the last pass ends at.
+    pub unsafe fn end_frame(&mut self, command_buffer: vk::CommandBuffer) {
+        if !self.current.is_empty() {
+            self.write(command_buffer);
+        }
+        self.passes[self.frame] = std::mem::take(&mut self.current);
+    }
+
+    unsafe fn write(&self, command_buffer: vk::CommandBuffer) {
+        self.ctx.device.cmd_write_timestamp2(
+            command_buffer,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            *self.pools[self.frame],
+            self.current.len() as u32,
+        );
+    }
+
+    // The timings of `frame`'s last recording, if there was one with any passes.
+    unsafe fn read(&mut self, frame: usize) -> Result<Option<GpuFrame>> {
+        let passes = std::mem::take(&mut self.passes[frame]);
+        if passes.is_empty() {
+            return Ok(None);
+        }
+        let mut ticks = vec![0_u64; passes.len() + 1];
+        match self.ctx.device.get_query_pool_results(
+            *self.pools[frame],
+            0,
+            &mut ticks,
+            vk::QueryResultFlags::TYPE_64,
+        ) {
+            Ok(()) => {}
+            // The frame was recorded but never submitted.
+            Err(vk::Result::NOT_READY) => return Ok(None),
+            Err(err) => return Err(err).check("vkGetQueryPoolResults"),
+        }
+
+        let ms =
+            |from: u64, to: u64| (to.wrapping_sub(from) & self.mask) as f64 * self.period / 1e6;
+        Ok(Some(GpuFrame {
+            recorded: self.recorded[frame],
+            start_ns: (ticks[0] & self.mask) as f64 * self.period,
+            passes: passes
+                .into_iter()
+                .zip(ticks.windows(2))
+                .map(|(name, span)| PassTime {
+                    name,
+                    start_ms: ms(ticks[0], span[0]),
+                    duration_ms: ms(span[0], span[1]),
+                })
+                .collect(),
+        }))
+    }
+}
+
+/// Memory the allocator holds in one heap.
+#[derive(Copy, Clone, Debug)]
+pub struct HeapUsage {
+    pub device_local: bool,
+    /// Bytes of `VkDeviceMemory` blocks allocated from the heap.
+    pub block_bytes: u64,
+    /// Bytes of those blocks in use by buffers and images.
+    pub allocation_bytes: u64,
+}
+
+/// Current memory usage of every heap, as tracked by the allocator.
+pub unsafe fn memory_usage(ctx: &Context) -> Result<Vec<HeapUsage>> {
+    let heaps = ctx.allocator.get_memory_properties().memory_heaps;
+    let budgets = ctx
+        .allocator
+        .get_heap_budgets()
+        .check("vmaGetHeapBudgets")?;
+    Ok(budgets
+        .iter()
+        .zip(heaps)
+        .map(|(budget, heap)| HeapUsage {
+            device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            block_bytes: budget.statistics.blockBytes,
+            allocation_bytes: budget.statistics.allocationBytes,
+        })
+        .collect())
+}
+
+/// Rolling averages of the CPU frame time and each pass's GPU time over the last `window`
+/// frames.
+pub struct FrameStats {
+    window: usize,
+    cpu_ms: VecDeque<f64>,
+    // In the order passes were first seen. Passes that only run on some frames are averaged
+    // over those.
+    passes: Vec<(&'static str, VecDeque<f64>)>,
+}
+
+impl FrameStats {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            cpu_ms: VecDeque::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn add_cpu(&mut self, frame: &CpuFrame) {
+        push(
+            &mut self.cpu_ms,
+            self.window,
+            frame.duration.as_secs_f64() * 1e3,
+        );
+    }
+
+    pub fn add_gpu(&mut self, frame: &GpuFrame) {
+        for pass in &frame.passes {
+            let index = match self.passes.iter().position(|(name, _)| *name == pass.name) {
+                Some(index) => index,
+                None => {
+                    self.passes.push((pass.name, VecDeque::new()));
+                    self.passes.len() - 1
+                }
+            };
+            push(&mut self.passes[index].1, self.window, pass.duration_ms);
+        }
+    }
+
+    pub fn cpu_ms(&self) -> Option<f64> {
+        average(&self.cpu_ms)
+    }
+
+    /// Average GPU time of each pass seen so far, in milliseconds.
+    pub fn pass_ms(&self) -> impl Iterator<Item = (&'static str, f64)> + '_ {
+        self.passes
+            .iter()
+            .filter_map(|(name, times)| Some((*name, average(times)?)))
+    }
+
+    /// e.g. `cpu 16.67 ms | gpu 2.31 ms (scene 1.80, tonemap 0.42)`.
+    pub fn summary(&self) -> String {
+        let mut summary = match self.cpu_ms() {
+            Some(cpu) => format!("cpu {cpu:.2} ms"),
+            None => "cpu -".to_string(),
+        };
+        let passes = self.pass_ms().collect::<Vec<_>>();
+        if !passes.is_empty() {
+            let total = passes.iter().map(|(_, ms)| ms).sum::<f64>();
+            let _ = write!(summary, " | gpu {total:.2} ms (");
+            for (i, (name, ms)) in passes.iter().enumerate() {
+                let separator = if i == 0 { "" } else { ", " };
+                let _ = write!(summary, "{separator}{name} {ms:.2}");
+            }
+            summary.push(')');
+        }
+        summary
+    }
+}
+
+fn push(times: &mut VecDeque<f64>, window: usize, time: f64) {
+    if times.len() == window {
+        times.pop_front();
+    }
+    times.push_back(time);
+}
+
+fn average(times: &VecDeque<f64>) -> Option<f64> {
+    (!times.is_empty()).then(|| times.iter().sum::<f64>() / times.len() as f64)
+}
+
+/// Frame timings in the Chrome trace event format, for `chrome://tracing` or Perfetto.
+///
+/// CPU frames and GPU passes go on separate tracks. The GPU clock is mapped onto the CPU one by
+/// lining up the first GPU frame with when it was recorded, so the tracks may drift apart
+/// slightly over long captures.
+pub struct ChromeTrace {
+    origin: Instant,
+    // Subtracted from GPU times to get microseconds since `origin`.
+    gpu_offset_us: Option<f64>,
+    events: Vec<TraceEvent>,
+}
+
+struct TraceEvent {
+    name: &'static str,
+    thread: u32,
+    start_us: f64,
+    duration_us: f64,
+}
+
+const CPU_THREAD: u32 = 1;
+const GPU_THREAD: u32 = 2;
+
+impl Default for ChromeTrace {
+    fn default() -> Self {
+        Self {
+            origin: Instant::now(),
+            gpu_offset_us: None,
+            events: Vec::new(),
+        }
+    }
+}
+
+impl ChromeTrace {
+    pub fn add_cpu(&mut self, frame: &CpuFrame) {
+        self.events.push(TraceEvent {
+            name: "frame",
+            thread: CPU_THREAD,
+            start_us: self.since_origin_us(frame.start),
+            duration_us: frame.duration.as_secs_f64() * 1e6,
+        });
+    }
+
+    pub fn add_gpu(&mut self, frame: &GpuFrame) {
+        let start_us = frame.start_ns / 1e3;
+        let recorded_us = self.since_origin_us(frame.recorded);
+        let offset = *self.gpu_offset_us.get_or_insert(start_us - recorded_us);
+        for pass in &frame.passes {
+            self.events.push(TraceEvent {
+                name: pass.name,
+                thread: GPU_THREAD,
+                start_us: start_us - offset + pass.start_ms * 1e3,
+                duration_us: pass.duration_ms * 1e3,
+            });
+        }
+    }
+
+    fn since_origin_us(&self, time: Instant) -> f64 {
+        time.saturating_duration_since(self.origin).as_secs_f64() * 1e6
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "{{\"displayTimeUnit\": \"ms\", \"traceEvents\": [")?;
+        for (thread, name) in [(CPU_THREAD, "CPU"), (GPU_THREAD, "GPU")] {
+            writeln!(
+                file,
+                "{{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": 1, \"tid\": {thread}, \
+                 \"args\": {{\"name\": \"{name}\"}}}},"
+            )?;
+        }
+        for (i, event) in self.events.iter().enumerate() {
+            let separator = if i + 1 == self.events.len() { "" } else { "," };
+            writeln!(
+                file,
+                "{{\"name\": {}, \"ph\": \"X\", \"pid\": 1, \"tid\": {}, \"ts\": {:.3}, \
+                 \"dur\": {:.3}}}{separator}",
+                json_string(event.name),
+                event.thread,
+                event.start_us,
+                event.duration_us,
+            )?;
+        }
+        writeln!(file, "]}}")?;
+        file.flush()
+    }
+}
+
+/// `value` as a quoted JSON string.
+pub fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(json_string("scene"), r#""scene""#);
+        assert_eq!(json_string("a \"b\" \\ c's"), r#""a \"b\" \\ c's""#);
+        assert_eq!(json_string("\n\t\u{7f}\u{1}"), r#""\n\t\u007f\u0001""#);
+        assert_eq!(json_string("llvmpipe (LLVM 15)"), r#""llvmpipe (LLVM 15)""#);
+    }
+}
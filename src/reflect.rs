@@ -0,0 +1,894 @@
+use ash::vk;
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Decoration, Dim, ExecutionModel, Op, StorageClass, Word};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::{Error, Result};
+
+/// The resources a shader stage reads, as declared in its SPIR-V.
+#[derive(Clone, Debug)]
+pub struct Reflection {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<Block>,
+    /// Vertex shader inputs, sorted by location.
+    pub inputs: Vec<VertexInput>,
+}
+
+#[derive(Clone, Debug)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    /// Zero for runtime-sized arrays, whose size only the layout can give.
+    pub count: u32,
+    /// Layout of uniform and storage buffers.
+    pub block: Option<Block>,
+}
+
+/// Layout of a uniform, storage or push constant block.
+#[derive(Clone, Debug)]
+pub struct Block {
+    pub name: String,
+    pub members: Vec<Member>,
+    /// Ends at the last member, without trailing padding.
+    pub size: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub name: String,
+    pub offset: u32,
+    /// Zero for a trailing runtime-sized array.
+    pub size: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VertexInput {
+    pub location: u32,
+    pub format: vk::Format,
+    pub size: u32,
+}
+
+/// Layout of a `#[repr(C)]` struct, from [`layout!`].
+pub struct StructLayout {
+    pub name: &'static str,
+    pub size: u32,
+    pub fields: Vec<Field>,
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Describes the listed fields of a `#[repr(C)]` struct, in declaration order, for
+/// [`Reflection::check_block`] and [`Reflection::check_push_constants`].
+macro_rules! layout {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        $crate::reflect::StructLayout {
+            name: stringify!($ty),
+            size: std::mem::size_of::<$ty>() as u32,
+            fields: vec![$($crate::reflect::Field {
+                name: stringify!($field),
+                offset: std::mem::offset_of!($ty, $field) as u32,
+                size: $crate::reflect::field_size(|value: &$ty| &value.$field),
+            }),*],
+        }
+    };
+}
+pub(crate) use layout;
+
+#[doc(hidden)]
+pub fn field_size<T, F>(_field: fn(&T) -> &F) -> u32 {
+    std::mem::size_of::<F>() as u32
+}
+
+impl Reflection {
+    /// Parses `spirv`; `name` identifies the shader in errors.
+    pub fn new(name: &str, spirv: &[u8]) -> Result<Self> {
+        let module = rspirv::dr::load_bytes(spirv).map_err(|err| Error::InvalidSpirv {
+            shader: name.into(),
+            message: err.to_string(),
+        })?;
+        Parser::new(name, &module).reflect()
+    }
+
+    pub fn binding(&self, set: u32, binding: u32) -> Option<&DescriptorBinding> {
+        self.bindings
+            .iter()
+            .find(|b| b.set == set && b.binding == binding)
+    }
+
+    /// Checks the uniform or storage block at `set` and `binding` against the struct it is
+    /// filled from. Passes if the shader doesn't use the binding.
+    pub fn check_block(&self, set: u32, binding: u32, layout: &StructLayout) -> Result<()> {
+        match self.binding(set, binding).and_then(|b| b.block.as_ref()) {
+            Some(block) => self.check_layout(block, layout),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks the push constant block against the struct pushed to it. Passes if the shader
+    /// has none.
+    pub fn check_push_constants(&self, layout: &StructLayout) -> Result<()> {
+        match &self.push_constants {
+            Some(block) => self.check_layout(block, layout),
+            None => Ok(()),
+        }
+    }
+
+    fn check_layout(&self, block: &Block, layout: &StructLayout) -> Result<()> {
+        let mismatch = |message: String| {
+            Err(Error::LayoutMismatch {
+                shader: self.name.clone(),
+                message,
+            })
+        };
+        if block.members.len() != layout.fields.len() {
+            return mismatch(format!(
+                "block {} has {} members but {} has {} fields",
+                block.name,
+                block.members.len(),
+                layout.name,
+                layout.fields.len()
+            ));
+        }
+        for (member, field) in block.members.iter().zip(&layout.fields) {
+            // A runtime-sized array takes up whatever is left of the struct.
+            let size_matches = member.size == field.size || member.size == 0;
+            if member.offset != field.offset || !size_matches {
+                return mismatch(format!(
+                    "{}.{} is {} bytes at offset {} but {}::{} is {} bytes at offset {}",
+                    block.name,
+                    member.name,
+                    member.size,
+                    member.offset,
+                    layout.name,
+                    field.name,
+                    field.size,
+                    field.offset
+                ));
+            }
+        }
+        if layout.size < block.size {
+            return mismatch(format!(
+                "block {} is {} bytes but {} is only {}",
+                block.name, block.size, layout.name, layout.size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Vertex input state that reads each input from its own tightly packed buffer, bound at
+    /// the input's location.
+    pub fn vertex_input(
+        &self,
+    ) -> (
+        Vec<vk::VertexInputBindingDescription>,
+        Vec<vk::VertexInputAttributeDescription>,
+    ) {
+        self.inputs
+            .iter()
+            .map(|input| {
+                (
+                    vk::VertexInputBindingDescription::default()
+                        .binding(input.location)
+                        .stride(input.size)
+                        .input_rate(vk::VertexInputRate::VERTEX),
+                    vk::VertexInputAttributeDescription::default()
+                        .binding(input.location)
+                        .location(input.location)
+                        .format(input.format)
+                        .offset(0),
+                )
+            })
+            .unzip()
+    }
+}
+
+/// Bindings of descriptor set `set` across `stages`, each visible to the stages that use it.
+/// Runtime-sized arrays keep a count of zero for the caller to fill in.
+pub fn set_layout_bindings(
+    stages: &[&Reflection],
+    set: u32,
+) -> Result<Vec<vk::DescriptorSetLayoutBinding<'static>>> {
+    let mut bindings = BTreeMap::<u32, vk::DescriptorSetLayoutBinding>::new();
+    for stage in stages {
+        for binding in stage.bindings.iter().filter(|b| b.set == set) {
+            let merged = bindings.entry(binding.binding).or_insert_with(|| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.descriptor_type)
+                    .descriptor_count(binding.count)
+            });
+            if merged.descriptor_type != binding.descriptor_type {
+                return Err(Error::LayoutMismatch {
+                    shader: stage.name.clone(),
+                    message: format!(
+                        "set {set} binding {} is a {:?} here but a {:?} in another stage",
+                        binding.binding, binding.descriptor_type, merged.descriptor_type
+                    ),
+                });
+            }
+            merged.descriptor_count = merged.descriptor_count.max(binding.count);
+            merged.stage_flags |= stage.stage;
+        }
+    }
+    Ok(bindings.into_values().collect())
+}
+
+/// A single push constant range covering every stage's block, or none if no stage has one.
+pub fn push_constant_ranges(stages: &[&Reflection]) -> Vec<vk::PushConstantRange> {
+    stages
+        .iter()
+        .filter_map(|stage| {
+            let block = stage.push_constants.as_ref()?;
+            let start = block.members.iter().map(|m| m.offset).min()?;
+            Some((stage.stage, start, block.size))
+        })
+        .reduce(|(stages, start, end), (stage, other_start, other_end)| {
+            (stages | stage, start.min(other_start), end.max(other_end))
+        })
+        .map(|(stages, start, end)| {
+            vk::PushConstantRange::default()
+                .stage_flags(stages)
+                .offset(start)
+                .size(end - start)
+        })
+        .into_iter()
+        .collect()
+}
+
+// Each with its literal operand, if any.
+type Decorations = Vec<(Decoration, Option<u32>)>;
+
+struct Parser<'a> {
+    name: &'a str,
+    module: &'a Module,
+    // Types, constants and global variables by id.
+    defs: HashMap<Word, &'a Instruction>,
+    names: HashMap<Word, &'a str>,
+    member_names: HashMap<(Word, u32), &'a str>,
+    decorations: HashMap<Word, Decorations>,
+    member_decorations: HashMap<(Word, u32), Decorations>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(name: &'a str, module: &'a Module) -> Self {
+        let defs = module
+            .types_global_values
+            .iter()
+            .filter_map(|inst| Some((inst.result_id?, inst)))
+            .collect();
+
+        let mut names = HashMap::new();
+        let mut member_names = HashMap::new();
+        for inst in &module.debug_names {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (Op::Name, [Operand::IdRef(id), Operand::LiteralString(name)]) => {
+                    names.insert(*id, name.as_str());
+                }
+                (
+                    Op::MemberName,
+                    [Operand::IdRef(id), Operand::LiteralInt32(member), Operand::LiteralString(name)],
+                ) => {
+                    member_names.insert((*id, *member), name.as_str());
+                }
+                _ => {}
+            }
+        }
+
+        let mut decorations = HashMap::<_, Vec<_>>::new();
+        let mut member_decorations = HashMap::<_, Vec<_>>::new();
+        for inst in &module.annotations {
+            match (inst.class.opcode, inst.operands.as_slice()) {
+                (
+                    Op::Decorate,
+                    [Operand::IdRef(id), Operand::Decoration(decoration), rest @ ..],
+                ) => {
+                    decorations
+                        .entry(*id)
+                        .or_default()
+                        .push((*decoration, literal(rest)));
+                }
+                (
+                    Op::MemberDecorate,
+                    [Operand::IdRef(id), Operand::LiteralInt32(member), Operand::Decoration(decoration), rest @ ..],
+                ) => {
+                    member_decorations
+                        .entry((*id, *member))
+                        .or_default()
+                        .push((*decoration, literal(rest)));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            name,
+            module,
+            defs,
+            names,
+            member_names,
+            decorations,
+            member_decorations,
+        }
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T> {
+        Err(Error::InvalidSpirv {
+            shader: self.name.into(),
+            message: message.into(),
+        })
+    }
+
+    fn reflect(&self) -> Result<Reflection> {
+        let Some(entry_point) = self.module.entry_points.first() else {
+            return self.error("no entry point");
+        };
+        let stage = match entry_point.operands.first() {
+            Some(Operand::ExecutionModel(model)) => match model {
+                ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+                ExecutionModel::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                ExecutionModel::TessellationEvaluation => {
+                    vk::ShaderStageFlags::TESSELLATION_EVALUATION
+                }
+                ExecutionModel::Geometry => vk::ShaderStageFlags::GEOMETRY,
+                ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+                ExecutionModel::GLCompute => vk::ShaderStageFlags::COMPUTE,
+                model => return self.error(format!("unsupported execution model {model:?}")),
+            },
+            _ => return self.error("entry point has no execution model"),
+        };
+
+        let mut reflection = Reflection {
+            name: self.name.into(),
+            stage,
+            bindings: Vec::new(),
+            push_constants: None,
+            inputs: Vec::new(),
+        };
+        for inst in &self.module.types_global_values {
+            let (Op::Variable, Some(id), Some(pointer)) =
+                (inst.class.opcode, inst.result_id, inst.result_type)
+            else {
+                continue;
+            };
+            let Some(Operand::StorageClass(storage)) = inst.operands.first() else {
+                return self.error(format!("variable {id} has no storage class"));
+            };
+            let ty = self.pointee(pointer)?;
+            match storage {
+                StorageClass::UniformConstant
+                | StorageClass::Uniform
+                | StorageClass::StorageBuffer => {
+                    reflection
+                        .bindings
+                        .push(self.descriptor_binding(id, *storage, ty)?);
+                }
+                StorageClass::PushConstant => {
+                    reflection.push_constants = Some(self.block(ty)?);
+                }
+                StorageClass::Input if stage == vk::ShaderStageFlags::VERTEX => {
+                    // Built-ins like gl_VertexIndex aren't read from vertex buffers.
+                    let Some(location) = self.decoration(id, Decoration::Location) else {
+                        continue;
+                    };
+                    let (format, size) = self.vertex_format(ty)?;
+                    reflection.inputs.push(VertexInput {
+                        location,
+                        format,
+                        size,
+                    });
+                }
+                _ => {}
+            }
+        }
+        reflection.bindings.sort_by_key(|b| (b.set, b.binding));
+        reflection.inputs.sort_by_key(|input| input.location);
+        Ok(reflection)
+    }
+
+    fn descriptor_binding(
+        &self,
+        variable: Word,
+        storage: StorageClass,
+        ty: Word,
+    ) -> Result<DescriptorBinding> {
+        let (Some(set), Some(binding)) = (
+            self.decoration(variable, Decoration::DescriptorSet),
+            self.decoration(variable, Decoration::Binding),
+        ) else {
+            return self.error(format!(
+                "{} has no descriptor set or binding",
+                self.name_of(variable)
+            ));
+        };
+
+        // Arrays of descriptors.
+        let (element, count) = match self.def(ty)? {
+            inst if inst.class.opcode == Op::TypeArray => {
+                (self.id_operand(inst, 0)?, self.array_length(inst)?)
+            }
+            inst if inst.class.opcode == Op::TypeRuntimeArray => (self.id_operand(inst, 0)?, 0),
+            _ => (ty, 1),
+        };
+
+        let element_def = self.def(element)?;
+        let mut block = None;
+        let descriptor_type = match (storage, element_def.class.opcode) {
+            (StorageClass::UniformConstant, Op::TypeSampledImage) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (StorageClass::UniformConstant, Op::TypeSampler) => vk::DescriptorType::SAMPLER,
+            (StorageClass::UniformConstant, Op::TypeImage) => {
+                // Operands: sampled type, dim, depth, arrayed, multisampled, sampled, format.
+                let Some(Operand::Dim(dim)) = element_def.operands.get(1) else {
+                    return self.error(format!("{} has no image dimension", self.name_of(element)));
+                };
+                let sampled = self.int_operand(element_def, 5)?;
+                match (dim, sampled) {
+                    (Dim::DimSubpassData, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                    (Dim::DimBuffer, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                    (Dim::DimBuffer, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                    (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                    _ => vk::DescriptorType::SAMPLED_IMAGE,
+                }
+            }
+            (StorageClass::Uniform | StorageClass::StorageBuffer, Op::TypeStruct) => {
+                block = Some(self.block(element)?);
+                // Before SPIR-V 1.3 storage buffers were uniforms decorated as BufferBlock.
+                let buffer_block = self.decoration(element, Decoration::BufferBlock).is_some();
+                if storage == StorageClass::StorageBuffer || buffer_block {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            }
+            (_, opcode) => {
+                return self.error(format!(
+                    "{} has unsupported type {opcode:?}",
+                    self.name_of(variable)
+                ))
+            }
+        };
+
+        Ok(DescriptorBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            block,
+        })
+    }
+
+    fn block(&self, ty: Word) -> Result<Block> {
+        let inst = self.def(ty)?;
+        if inst.class.opcode != Op::TypeStruct {
+            return self.error(format!("{} is not a struct", self.name_of(ty)));
+        }
+        let members = (0..inst.operands.len())
+            .map(|index| {
+                let member_ty = self.id_operand(inst, index)?;
+                let index = index as u32;
+                let Some(offset) = self.member_decoration(ty, index, Decoration::Offset) else {
+                    return self
+                        .error(format!("{} member {index} has no offset", self.name_of(ty)));
+                };
+                // Matrices in blocks are laid out by their stride rather than their column type.
+                let size = match (
+                    self.def(member_ty)?,
+                    self.member_decoration(ty, index, Decoration::MatrixStride),
+                ) {
+                    (matrix, Some(stride)) if matrix.class.opcode == Op::TypeMatrix => {
+                        self.int_operand(matrix, 1)? * stride
+                    }
+                    _ => self.size(member_ty)?,
+                };
+                Ok(Member {
+                    name: self
+                        .member_names
+                        .get(&(ty, index))
+                        .map_or_else(|| index.to_string(), |name| name.to_string()),
+                    offset,
+                    size,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Block {
+            name: self.name_of(ty),
+            size: members.iter().map(|m| m.offset + m.size).max().unwrap_or(0),
+            members,
+        })
+    }
+
+    // Size of a type inside a block.
+    fn size(&self, ty: Word) -> Result<u32> {
+        let inst = self.def(ty)?;
+        Ok(match inst.class.opcode {
+            Op::TypeBool => 4,
+            Op::TypeInt | Op::TypeFloat => self.int_operand(inst, 0)? / 8,
+            Op::TypeVector | Op::TypeMatrix => {
+                self.int_operand(inst, 1)? * self.size(self.id_operand(inst, 0)?)?
+            }
+            Op::TypeArray => {
+                let stride = match self.decoration(ty, Decoration::ArrayStride) {
+                    Some(stride) => stride,
+                    None => self.size(self.id_operand(inst, 0)?)?,
+                };
+                self.array_length(inst)? * stride
+            }
+            Op::TypeRuntimeArray => 0,
+            Op::TypeStruct => self.block(ty)?.size,
+            opcode => return self.error(format!("{opcode:?} has no size")),
+        })
+    }
+
+    fn vertex_format(&self, ty: Word) -> Result<(vk::Format, u32)> {
+        let inst = self.def(ty)?;
+        let (scalar, count) = match inst.class.opcode {
+            Op::TypeVector => (
+                self.def(self.id_operand(inst, 0)?)?,
+                self.int_operand(inst, 1)?,
+            ),
+            _ => (inst, 1),
+        };
+        let format = match (scalar.class.opcode, scalar.operands.as_slice(), count) {
+            (Op::TypeFloat, [Operand::LiteralInt32(32)], 1) => vk::Format::R32_SFLOAT,
+            (Op::TypeFloat, [Operand::LiteralInt32(32)], 2) => vk::Format::R32G32_SFLOAT,
+            (Op::TypeFloat, [Operand::LiteralInt32(32)], 3) => vk::Format::R32G32B32_SFLOAT,
+            (Op::TypeFloat, [Operand::LiteralInt32(32)], 4) => vk::Format::R32G32B32A32_SFLOAT,
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(1)], 1) => {
+                vk::Format::R32_SINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(1)], 2) => {
+                vk::Format::R32G32_SINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(1)], 3) => {
+                vk::Format::R32G32B32_SINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(1)], 4) => {
+                vk::Format::R32G32B32A32_SINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(0)], 1) => {
+                vk::Format::R32_UINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(0)], 2) => {
+                vk::Format::R32G32_UINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(0)], 3) => {
+                vk::Format::R32G32B32_UINT
+            }
+            (Op::TypeInt, [Operand::LiteralInt32(32), Operand::LiteralInt32(0)], 4) => {
+                vk::Format::R32G32B32A32_UINT
+            }
+            _ => {
+                return self.error(format!(
+                    "unsupported vertex input type {}",
+                    self.name_of(ty)
+                ))
+            }
+        };
+        Ok((format, count * 4))
+    }
+
+    fn pointee(&self, pointer: Word) -> Result<Word> {
+        match self.def(pointer)? {
+            inst if inst.class.opcode == Op::TypePointer => self.id_operand(inst, 1),
+            _ => self.error(format!("{pointer} is not a pointer")),
+        }
+    }
+
+    fn array_length(&self, array: &Instruction) -> Result<u32> {
+        let length = self.id_operand(array, 1)?;
+        match self.def(length)?.operands.as_slice() {
+            [Operand::LiteralInt32(length)] => Ok(*length),
+            _ => self.error(format!("array length {length} is not a constant")),
+        }
+    }
+
+    fn def(&self, id: Word) -> Result<&'a Instruction> {
+        match self.defs.get(&id) {
+            Some(inst) => Ok(inst),
+            None => self.error(format!("{id} is not defined")),
+        }
+    }
+
+    fn id_operand(&self, inst: &Instruction, index: usize) -> Result<Word> {
+        match inst.operands.get(index) {
+            Some(Operand::IdRef(id)) => Ok(*id),
+            _ => self.error(format!(
+                "{:?} operand {index} is not an id",
+                inst.class.opcode
+            )),
+        }
+    }
+
+    fn int_operand(&self, inst: &Instruction, index: usize) -> Result<u32> {
+        match inst.operands.get(index) {
+            Some(Operand::LiteralInt32(value)) => Ok(*value),
+            _ => self.error(format!(
+                "{:?} operand {index} is not an integer",
+                inst.class.opcode
+            )),
+        }
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<u32> {
+        find_decoration(self.decorations.get(&id)?, decoration)
+    }
+
+    fn member_decoration(&self, id: Word, member: u32, decoration: Decoration) -> Option<u32> {
+        find_decoration(self.member_decorations.get(&(id, member))?, decoration)
+    }
+
+    // Debug name, falling back to the id.
+    fn name_of(&self, id: Word) -> String {
+        match self.names.get(&id) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => format!("%{id}"),
+        }
+    }
+}
+
+// Decorations without a literal, like Block, are found with a value of 0.
+fn find_decoration(
+    decorations: &[(Decoration, Option<u32>)],
+    decoration: Decoration,
+) -> Option<u32> {
+    decorations
+        .iter()
+        .find(|(d, _)| *d == decoration)
+        .map(|(_, value)| value.unwrap_or(0))
+}
+
+fn literal(operands: &[Operand]) -> Option<u32> {
+    match operands.first() {
+        Some(Operand::LiteralInt32(value)) => Some(*value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflect(name: &str) -> Reflection {
+        let path = format!(
+            "{}/resources/shaders/spirv/{name}.spv",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        Reflection::new(name, &std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn members(block: &Block) -> Vec<(&str, u32, u32)> {
+        block
+            .members
+            .iter()
+            .map(|m| (m.name.as_str(), m.offset, m.size))
+            .collect()
+    }
+
+    #[test]
+    fn scene_shaders() {
+        let vert = reflect("shader.vert");
+        let frag = reflect("shader.frag");
+        assert_eq!(vert.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(frag.stage, vk::ShaderStageFlags::FRAGMENT);
+
+        let global = vert.binding(0, 0).unwrap();
+        assert_eq!(global.descriptor_type, vk::DescriptorType::UNIFORM_BUFFER);
+        let global = global.block.as_ref().unwrap();
+        assert_eq!(members(global), [("proj", 0, 64), ("view", 64, 64)]);
+        let samplers = frag.binding(0, 1).unwrap();
+        assert_eq!(
+            samplers.descriptor_type,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+        );
+        // Sized by the highest index used; the renderer widens it to its texture limit.
+        assert_eq!(samplers.count, 1);
+
+        let bindings = set_layout_bindings(&[&vert, &frag], 0).unwrap();
+        let bindings = bindings
+            .iter()
+            .map(|b| (b.binding, b.descriptor_type, b.stage_flags))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            [
+                (
+                    0,
+                    vk::DescriptorType::UNIFORM_BUFFER,
+                    vk::ShaderStageFlags::VERTEX
+                ),
+                (
+                    1,
+                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    vk::ShaderStageFlags::FRAGMENT
+                ),
+            ]
+        );
+
+        assert_eq!(
+            members(vert.push_constants.as_ref().unwrap()),
+            [("model", 0, 64)]
+        );
+        assert!(frag.push_constants.is_none());
+        let ranges = push_constant_ranges(&[&vert, &frag]);
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX);
+        assert_eq!((ranges[0].offset, ranges[0].size), (0, 64));
+
+        let inputs = vert
+            .inputs
+            .iter()
+            .map(|input| (input.location, input.format, input.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inputs,
+            [
+                (0, vk::Format::R32G32B32_SFLOAT, 12),
+                (1, vk::Format::R32G32_SFLOAT, 8),
+            ]
+        );
+        assert!(frag.inputs.is_empty());
+    }
+
+    #[test]
+    fn post_shaders() {
+        let stages = [
+            "tonemap.vert",
+            "tonemap.frag",
+            "histogram.comp",
+            "exposure.comp",
+        ]
+        .map(reflect);
+        let [vert, frag, histogram, exposure] = &stages;
+        assert_eq!(vert.stage, vk::ShaderStageFlags::VERTEX);
+        assert!(vert.inputs.is_empty() && vert.bindings.is_empty());
+        assert_eq!(histogram.stage, vk::ShaderStageFlags::COMPUTE);
+
+        for shader in [frag, histogram, exposure] {
+            let buffer = shader.binding(0, 1).unwrap();
+            assert_eq!(buffer.descriptor_type, vk::DescriptorType::STORAGE_BUFFER);
+            assert_eq!(
+                members(buffer.block.as_ref().unwrap()),
+                [("histogram", 0, 1024), ("exposure", 1024, 4)]
+            );
+            let constants = shader.push_constants.as_ref().unwrap();
+            assert_eq!(constants.members.len(), 7);
+            assert_eq!(constants.size, 28);
+        }
+
+        let bindings = set_layout_bindings(&stages.each_ref(), 0).unwrap();
+        let all = vk::ShaderStageFlags::FRAGMENT | vk::ShaderStageFlags::COMPUTE;
+        let bindings = bindings
+            .iter()
+            .map(|b| {
+                (
+                    b.binding,
+                    b.descriptor_type,
+                    b.descriptor_count,
+                    b.stage_flags,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            [
+                (0, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, all),
+                (1, vk::DescriptorType::STORAGE_BUFFER, 1, all),
+            ]
+        );
+
+        let ranges = push_constant_ranges(&stages.each_ref());
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].stage_flags, all);
+        assert_eq!((ranges[0].offset, ranges[0].size), (0, 28));
+    }
+
+    #[test]
+    fn layout_mismatch() {
+        #[repr(C)]
+        struct Global {
+            proj: [f32; 16],
+            padding: [f32; 4],
+            view: [f32; 16],
+        }
+        #[repr(C)]
+        struct Matrices {
+            proj: [f32; 16],
+            view: [f32; 16],
+        }
+        #[repr(C)]
+        struct Constants {
+            model: [f32; 12],
+        }
+
+        let vert = reflect("shader.vert");
+        vert.check_block(0, 0, &layout!(Matrices { proj, view }))
+            .unwrap();
+        // A misplaced member, a missing one and a short one.
+        for result in [
+            vert.check_block(0, 0, &layout!(Global { proj, view })),
+            vert.check_block(0, 0, &layout!(Matrices { proj })),
+            vert.check_push_constants(&layout!(Constants { model })),
+        ] {
+            assert!(matches!(result, Err(Error::LayoutMismatch { .. })));
+        }
+    }
+
+    #[test]
+    fn invalid_spirv() {
+        for spirv in [&[][..], &[0x03, 0x02, 0x23], &[0; 20]] {
+            assert!(matches!(
+                Reflection::new("garbage", spirv),
+                Err(Error::InvalidSpirv { .. })
+            ));
+        }
+
+        // A vertex shader whose input has a pointer or vector type missing an operand.
+        let inst = |op, result_type, result_id, operands| {
+            Instruction::new(op, result_type, Some(result_id), operands)
+        };
+        let malformed = [
+            vec![inst(
+                Op::TypePointer,
+                None,
+                2,
+                vec![Operand::StorageClass(StorageClass::Input)],
+            )],
+            vec![
+                inst(Op::TypeFloat, None, 2, vec![Operand::LiteralInt32(32)]),
+                inst(Op::TypeVector, None, 3, vec![Operand::IdRef(2)]),
+                inst(
+                    Op::TypePointer,
+                    None,
+                    4,
+                    vec![
+                        Operand::StorageClass(StorageClass::Input),
+                        Operand::IdRef(3),
+                    ],
+                ),
+            ],
+        ];
+        for types in malformed {
+            let pointer = types.last().unwrap().result_id.unwrap();
+            let mut module = Module::new();
+            module.entry_points.push(Instruction::new(
+                Op::EntryPoint,
+                None,
+                None,
+                vec![
+                    Operand::ExecutionModel(ExecutionModel::Vertex),
+                    Operand::IdRef(1),
+                    Operand::LiteralString("main".into()),
+                ],
+            ));
+            module.annotations.push(Instruction::new(
+                Op::Decorate,
+                None,
+                None,
+                vec![
+                    Operand::IdRef(10),
+                    Operand::Decoration(Decoration::Location),
+                    Operand::LiteralInt32(0),
+                ],
+            ));
+            module.types_global_values = types;
+            module.types_global_values.push(inst(
+                Op::Variable,
+                Some(pointer),
+                10,
+                vec![Operand::StorageClass(StorageClass::Input)],
+            ));
+            assert!(matches!(
+                Parser::new("malformed", &module).reflect(),
+                Err(Error::InvalidSpirv { .. })
+            ));
+        }
+    }
+}
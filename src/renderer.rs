@@ -0,0 +1,1567 @@
+use ash::vk;
+use glam::*;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::Instant;
+use winit::raw_window_handle::{
+    HasDisplayHandle, HasWindowHandle, RawDisplayHandle, RawWindowHandle,
+};
+
+use crate::capture::FrameCapture;
+use crate::context::{
+    Buffer, CommandPool, Context, DescriptorPool, DescriptorSetLayout, Image, ImageView, LeakCheck,
+    Owned, Pipeline, PipelineCache, PipelineLayout, Sampler, Semaphore, ShaderModule, SyncPoint,
+};
+use crate::debug::Severity;
+use crate::error::{Check, Error, Result};
+use crate::graph::{
+    BufferAccess, ImageAccess, RenderGraph, ResourceState, TransientDesc, TransientImages,
+};
+use crate::pipeline::{Blend, GraphicsPipelines, PipelineDesc, Targets};
+use crate::pipeline_cache;
+use crate::profiler::{CpuFrame, GpuFrame, GpuTimer};
+use crate::reflect::{self, layout, Reflection};
+use crate::staging::{Readback, ReadbackBuffer};
+use crate::streaming::{AssetId, GpuMesh, GpuTexture, Texture};
+use crate::swapchain::Swapchain;
+use crate::targets::{clamp_samples, DEPTH_FORMAT};
+use crate::upload::Uploader;
+
+// SPIR-V the build script compiled from `resources/shaders`.
+macro_rules! spirv {
+    ($shader:literal) => {
+        include_bytes!(concat!(env!("OUT_DIR"), "/", $shader, ".spv"))
+    };
+}
+
+/// Frames recorded ahead of the GPU unless configured otherwise.
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 3;
+const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// Size of the bindless texture array, which shaders declare unsized.
+const MAX_TEXTURES: u32 = 1024;
+
+/// Upper bound on streamed bytes staged per frame.
+const STREAM_BUDGET: u64 = 1 << 20;
+
+/// Asset id of the texture shown in place of ones still streaming in.
+const PLACEHOLDER_TEXTURE: AssetId = AssetId::MAX;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct GlobalDescriptorSet {
+    proj: Mat4,
+    view: Mat4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SceneConstants {
+    model: Mat4,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct ExposureBuffer {
+    histogram: [u32; 256],
+    exposure: f32,
+}
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug)]
+pub enum Tonemap {
+    AcesFitted,
+    Reinhard,
+    Agx,
+}
+
+impl Tonemap {
+    pub fn next(self) -> Self {
+        match self {
+            Tonemap::AcesFitted => Tonemap::Reinhard,
+            Tonemap::Reinhard => Tonemap::Agx,
+            Tonemap::Agx => Tonemap::AcesFitted,
+        }
+    }
+}
+
+// Shared by the histogram, exposure and tonemap shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct PostConstants {
+    min_log_lum: f32,
+    log_lum_range: f32,
+    dt: f32,
+    exposure_ev: f32,
+    tonemap: u32,
+    auto_exposure: u32,
+    encode_srgb: u32,
+}
+
+// A fullscreen triangle without depth.
+const TONEMAP_PIPELINE: PipelineDesc = PipelineDesc {
+    topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+    polygon_mode: vk::PolygonMode::FILL,
+    cull_mode: vk::CullModeFlags::NONE,
+    front_face: vk::FrontFace::CLOCKWISE,
+    depth_test: false,
+    depth_write: false,
+    depth_compare_op: vk::CompareOp::ALWAYS,
+    blend: Blend::Opaque,
+    color_write: true,
+};
+
+fn scene_targets(samples: vk::SampleCountFlags) -> Targets {
+    Targets {
+        color_format: HDR_FORMAT,
+        depth_format: Some(DEPTH_FORMAT),
+        samples,
+    }
+}
+
+// Checks a scene shader's blocks against the structs we fill them from.
+fn check_scene_shader(shader: &Reflection) -> Result<()> {
+    shader.check_block(0, 0, &layout!(GlobalDescriptorSet { proj, view }))?;
+    shader.check_push_constants(&layout!(SceneConstants { model }))
+}
+
+// Checks a post-process shader's blocks against the structs we fill them from.
+fn check_post_shader(shader: &Reflection) -> Result<()> {
+    shader.check_block(
+        0,
+        1,
+        &layout!(ExposureBuffer {
+            histogram,
+            exposure
+        }),
+    )?;
+    shader.check_push_constants(&layout!(PostConstants {
+        min_log_lum,
+        log_lum_range,
+        dt,
+        exposure_ev,
+        tonemap,
+        auto_exposure,
+        encode_srgb,
+    }))
+}
+
+// Pushes the part of `constants` each reflected range covers, to the stages that read it.
+unsafe fn push_constants<T>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    layout: vk::PipelineLayout,
+    ranges: &[vk::PushConstantRange],
+    constants: &T,
+) {
+    let bytes = std::slice::from_raw_parts(constants as *const T as *const u8, size_of::<T>());
+    for range in ranges {
+        let start = range.offset as usize;
+        device.cmd_push_constants(
+            command_buffer,
+            layout,
+            range.stage_flags,
+            range.offset,
+            &bytes[start..start + range.size as usize],
+        );
+    }
+}
+
+/// Settings fixed for the life of a [`Renderer`].
+pub struct RendererConfig {
+    pub extent: vk::Extent2D,
+    /// Requested MSAA sample count, clamped to what the device supports.
+    pub msaa_samples: u32,
+    /// Without vsync, an immediate or mailbox present mode is used when available.
+    pub vsync: bool,
+    /// Enables the validation layers, if installed, logging messages of at least this severity.
+    pub validation: Option<Severity>,
+    /// Pipeline cache file, loaded at startup and saved when the renderer is dropped. `None`
+    /// keeps the cache in memory only.
+    pub pipeline_cache: Option<PathBuf>,
+    /// How many frames the CPU may record while earlier ones are still on the GPU, independent
+    /// of the swapchain's image count. At least 1.
+    pub frames_in_flight: usize,
+}
+
+/// Post-process controls.
+#[derive(Copy, Clone, Debug)]
+pub struct PostSettings {
+    pub tonemap: Tonemap,
+    pub exposure_ev: f32,
+    pub auto_exposure: bool,
+}
+
+/// What to draw in a frame.
+pub struct Frame<'a> {
+    pub view: Mat4,
+    pub model: Mat4,
+    /// Meshes still streaming in are skipped.
+    pub mesh: Option<&'a GpuMesh>,
+    /// Textures still streaming in are replaced by a placeholder.
+    pub texture: Option<&'a GpuTexture>,
+    pub post: PostSettings,
+    /// How the mesh is drawn, e.g. [`Variant::Wireframe`](crate::Variant::Wireframe).
+    pub pipeline: PipelineDesc,
+    pub dt: f32,
+    /// Copy the presented image back to the host, if
+    /// [`check_capture`](Renderer::check_capture) passes.
+    pub capture: bool,
+}
+
+/// Results of a frame that reached the host.
+#[derive(Default)]
+pub struct FrameOutput {
+    /// The exposure the GPU last settled on, when a new readback arrived.
+    pub exposure: Option<f32>,
+    /// RGBA8 pixels of an earlier captured frame.
+    pub capture: Option<Vec<u8>>,
+    /// Time since the previous frame began recording.
+    pub cpu_frame: Option<CpuFrame>,
+    /// Per-pass GPU timings of an earlier frame, when its timestamps arrived.
+    pub gpu_timings: Option<GpuFrame>,
+}
+
+// Resources each frame in flight has its own copy of.
+struct FrameResources {
+    command_buffer: vk::CommandBuffer,
+    // Signaled by acquiring the frame's swapchain image.
+    image_available: Semaphore,
+    global_set: vk::DescriptorSet,
+    matrix_buffer: Buffer,
+    // The frame timeline value these are free again at.
+    done: u64,
+}
+
+/// Draws a textured mesh into an HDR target, then tonemaps it to the window.
+pub struct Renderer {
+    frame: usize,
+    // Signaled with increasing values as frames finish on the GPU.
+    frame_timeline: Semaphore,
+    frame_value: u64,
+    frames: Vec<FrameResources>,
+    // `None` if the device can't write timestamps.
+    gpu_timer: Option<GpuTimer>,
+    last_render: Option<Instant>,
+    _command_pool: CommandPool,
+
+    post_set: vk::DescriptorSet,
+    _descriptor_pool: DescriptorPool,
+
+    placeholder: GpuTexture,
+    // Assets whose uploads have finished.
+    ready: HashSet<AssetId>,
+    uploader: Uploader,
+    readback_buffer: ReadbackBuffer,
+    frame_capture: FrameCapture,
+    exposure_readback: Option<Readback<f32>>,
+    exposure_buffer: Buffer,
+    texture_sampler: Sampler,
+
+    pipeline_cache: PipelineCache,
+    pipeline_cache_path: Option<PathBuf>,
+    scene_pipelines: GraphicsPipelines,
+    pipeline_layout: PipelineLayout,
+    scene_constant_ranges: Vec<vk::PushConstantRange>,
+    _global_set_layout: DescriptorSetLayout,
+    vert_shader: ShaderModule,
+    frag_shader: ShaderModule,
+    tonemap_pipelines: GraphicsPipelines,
+    tonemap_vert_shader: ShaderModule,
+    tonemap_frag_shader: ShaderModule,
+    histogram_pipeline: Pipeline,
+    exposure_pipeline: Pipeline,
+    post_pipeline_layout: PipelineLayout,
+    post_constant_ranges: Vec<vk::PushConstantRange>,
+    _post_set_layout: DescriptorSetLayout,
+
+    supported_samples: vk::SampleCountFlags,
+    samples: vk::SampleCountFlags,
+    transients: TransientImages,
+    hdr_sampler: Sampler,
+    // Declared first so it is dropped before the image.
+    hdr_view: ImageView,
+    hdr_image: Image,
+    swapchain: Swapchain,
+
+    // Every object above is dropped before the leak check runs.
+    _leak_check: LeakCheck,
+    ctx: Rc<Context>,
+}
+
+impl Renderer {
+    pub unsafe fn new(
+        window: &(impl HasDisplayHandle + HasWindowHandle),
+        config: RendererConfig,
+    ) -> Result<Self> {
+        Self::create(
+            Some((
+                window.display_handle()?.as_raw(),
+                window.window_handle()?.as_raw(),
+            )),
+            config,
+        )
+    }
+
+    /// Renders into offscreen images instead of a window, e.g. for benchmarks on machines
+    /// without a display. Frames can still be captured.
+    pub unsafe fn headless(config: RendererConfig) -> Result<Self> {
+        Self::create(None, config)
+    }
+
+    unsafe fn create(
+        window: Option<(RawDisplayHandle, RawWindowHandle)>,
+        config: RendererConfig,
+    ) -> Result<Self> {
+        let frames = config.frames_in_flight;
+        assert!(frames > 0, "At least one frame must be in flight.");
+
+        // Debug builds also track where each object was created, to report leaks.
+        let (ctx, surface) =
+            crate::device::create(window, config.validation, cfg!(debug_assertions))?;
+        let leak_check = ctx.leak_check();
+        let device = &ctx.device;
+        let swapchain = Swapchain::new(&ctx, surface, config.extent, config.vsync)?;
+        // The surface may have picked a different size than the window's.
+        let extent = swapchain.extent;
+
+        // Multisampled color and depth, allocated by the render graph.
+        let supported_samples = {
+            let limits = ctx
+                .instance
+                .get_physical_device_properties(ctx.pdevice)
+                .limits;
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts
+        };
+        let samples = clamp_samples(config.msaa_samples, supported_samples);
+
+        // Linear HDR color target, resolved to the swapchain by the tonemap pass.
+        let (hdr_image, hdr_view) = create_hdr_target(&ctx, extent)?;
+
+        let hdr_sampler = Owned::new(
+            &ctx,
+            device
+                .create_sampler(
+                    &vk::SamplerCreateInfo::default()
+                        .mag_filter(vk::Filter::NEAREST)
+                        .min_filter(vk::Filter::NEAREST)
+                        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .unnormalized_coordinates(false)
+                        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+                        .max_lod(0.0),
+                    None,
+                )
+                .check("vkCreateSampler")?,
+        );
+
+        let pipeline_cache = pipeline_cache::load(&ctx, config.pipeline_cache.as_deref())?;
+
+        // Layouts follow the shaders.
+        let vert_reflection = Reflection::new("shader.vert", spirv!("shader.vert"))?;
+        let frag_reflection = Reflection::new("shader.frag", spirv!("shader.frag"))?;
+        check_scene_shader(&vert_reflection)?;
+        check_scene_shader(&frag_reflection)?;
+        let scene_stages = [&vert_reflection, &frag_reflection];
+
+        // Global descriptor set.
+        let global_bindings = reflect::set_layout_bindings(&scene_stages, 0)?
+            .into_iter()
+            .map(|binding| match binding.descriptor_type {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER => {
+                    binding.descriptor_count(MAX_TEXTURES)
+                }
+                _ => binding,
+            })
+            .collect::<Vec<_>>();
+        let global_set_layout = Owned::new(
+            &ctx,
+            device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default()
+                        .push_next(
+                            &mut vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+                                .binding_flags(&vec![
+                                    vk::DescriptorBindingFlags::PARTIALLY_BOUND
+                                        | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND;
+                                    global_bindings.len()
+                                ]),
+                        )
+                        .bindings(&global_bindings)
+                        .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL),
+                    None,
+                )
+                .check("vkCreateDescriptorSetLayout")?,
+        );
+
+        let vert_shader = ctx.create_shader_module(spirv!("shader.vert"))?;
+        let frag_shader = ctx.create_shader_module(spirv!("shader.frag"))?;
+
+        let scene_constant_ranges = reflect::push_constant_ranges(&scene_stages);
+        let pipeline_layout = Owned::new(
+            &ctx,
+            device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::default()
+                        .set_layouts(&[*global_set_layout])
+                        .push_constant_ranges(&scene_constant_ranges),
+                    None,
+                )
+                .check("vkCreatePipelineLayout")?,
+        );
+
+        let mut scene_pipelines = GraphicsPipelines::new(
+            &ctx,
+            "scene",
+            *pipeline_cache,
+            *pipeline_layout,
+            (*vert_shader, &vert_reflection),
+            *frag_shader,
+            scene_targets(samples),
+        );
+        // Variants are built as they are drawn; build the default one now to fail early.
+        scene_pipelines.get(&PipelineDesc::default())?;
+
+        let post_reflections = [
+            Reflection::new("tonemap.vert", spirv!("tonemap.vert"))?,
+            Reflection::new("tonemap.frag", spirv!("tonemap.frag"))?,
+            Reflection::new("histogram.comp", spirv!("histogram.comp"))?,
+            Reflection::new("exposure.comp", spirv!("exposure.comp"))?,
+        ];
+        for shader in &post_reflections {
+            check_post_shader(shader)?;
+        }
+        let post_stages = post_reflections.each_ref();
+
+        // Post-process descriptor set.
+        let post_set_layout = Owned::new(
+            &ctx,
+            device
+                .create_descriptor_set_layout(
+                    &vk::DescriptorSetLayoutCreateInfo::default()
+                        .bindings(&reflect::set_layout_bindings(&post_stages, 0)?),
+                    None,
+                )
+                .check("vkCreateDescriptorSetLayout")?,
+        );
+
+        let tonemap_vert_shader = ctx.create_shader_module(spirv!("tonemap.vert"))?;
+        let tonemap_frag_shader = ctx.create_shader_module(spirv!("tonemap.frag"))?;
+        // Only needed while creating the pipelines.
+        let histogram_shader = ctx.create_shader_module(spirv!("histogram.comp"))?;
+        let exposure_shader = ctx.create_shader_module(spirv!("exposure.comp"))?;
+
+        let post_constant_ranges = reflect::push_constant_ranges(&post_stages);
+        let post_pipeline_layout = Owned::new(
+            &ctx,
+            device
+                .create_pipeline_layout(
+                    &vk::PipelineLayoutCreateInfo::default()
+                        .set_layouts(&[*post_set_layout])
+                        .push_constant_ranges(&post_constant_ranges),
+                    None,
+                )
+                .check("vkCreatePipelineLayout")?,
+        );
+
+        let mut tonemap_pipelines = GraphicsPipelines::new(
+            &ctx,
+            "tonemap",
+            *pipeline_cache,
+            *post_pipeline_layout,
+            (*tonemap_vert_shader, &post_reflections[0]),
+            *tonemap_frag_shader,
+            Targets {
+                color_format: swapchain.format.format,
+                depth_format: None,
+                samples: vk::SampleCountFlags::TYPE_1,
+            },
+        );
+        tonemap_pipelines.get(&TONEMAP_PIPELINE)?;
+
+        let create_compute_pipeline = |shader: vk::ShaderModule, name| -> Result<_> {
+            let pipeline = ctx.create_compute_pipeline(
+                *pipeline_cache,
+                &vk::ComputePipelineCreateInfo::default()
+                    .stage(
+                        vk::PipelineShaderStageCreateInfo::default()
+                            .module(shader)
+                            .stage(vk::ShaderStageFlags::COMPUTE)
+                            .name(c"main"),
+                    )
+                    .layout(*post_pipeline_layout),
+            )?;
+            ctx.set_name(*pipeline, name)?;
+            Ok(pipeline)
+        };
+        let histogram_pipeline = create_compute_pipeline(*histogram_shader, "histogram")?;
+        let exposure_pipeline = create_compute_pipeline(*exposure_shader, "exposure")?;
+
+        let descriptor_pool = Owned::new(
+            &ctx,
+            device
+                .create_descriptor_pool(
+                    &vk::DescriptorPoolCreateInfo::default()
+                        .pool_sizes(&[
+                            vk::DescriptorPoolSize::default()
+                                .ty(vk::DescriptorType::UNIFORM_BUFFER)
+                                .descriptor_count(frames as u32),
+                            vk::DescriptorPoolSize::default()
+                                .ty(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                                .descriptor_count(frames as u32 * MAX_TEXTURES + 1),
+                            vk::DescriptorPoolSize::default()
+                                .ty(vk::DescriptorType::STORAGE_BUFFER)
+                                .descriptor_count(1),
+                        ])
+                        .max_sets(frames as u32 + 1)
+                        .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND),
+                    None,
+                )
+                .check("vkCreateDescriptorPool")?,
+        );
+
+        let global_sets = device
+            .allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(*descriptor_pool)
+                    .set_layouts(&vec![*global_set_layout; frames]),
+            )
+            .check("vkAllocateDescriptorSets")?;
+
+        let post_set = device
+            .allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(*descriptor_pool)
+                    .set_layouts(&[*post_set_layout]),
+            )
+            .check("vkAllocateDescriptorSets")?[0];
+
+        let command_pool = Owned::new(
+            &ctx,
+            device
+                .create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(ctx.queues.graphics_family)
+                        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+                    None,
+                )
+                .check("vkCreateCommandPool")?,
+        );
+
+        let command_buffers = device
+            .allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(*command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(frames as u32),
+            )
+            .check("vkAllocateCommandBuffers")?;
+
+        let texture_sampler = Owned::new(
+            &ctx,
+            device
+                .create_sampler(
+                    &vk::SamplerCreateInfo::default()
+                        .mag_filter(vk::Filter::LINEAR)
+                        .min_filter(vk::Filter::LINEAR)
+                        .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+                        .unnormalized_coordinates(false)
+                        .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+                        .mip_lod_bias(0.0)
+                        .min_lod(0.0)
+                        .max_lod(0.0),
+                    None,
+                )
+                .check("vkCreateSampler")?,
+        );
+
+        let mut uploader = Uploader::new(&ctx, 10000000, frames)?;
+        let readback_buffer = create_readback_buffer(&ctx, extent)?;
+        let frame_capture = FrameCapture::new(extent, swapchain.format.format);
+
+        // Each frame uploads its own matrices, so earlier frames can still read theirs.
+        let frame_resources = command_buffers
+            .into_iter()
+            .zip(global_sets)
+            .enumerate()
+            .map(|(frame, (command_buffer, global_set))| {
+                let matrix_buffer = Buffer::new(
+                    &ctx,
+                    &vk::BufferCreateInfo::default()
+                        .size(size_of::<GlobalDescriptorSet>() as u64)
+                        .usage(
+                            vk::BufferUsageFlags::UNIFORM_BUFFER
+                                | vk::BufferUsageFlags::TRANSFER_DST,
+                        )
+                        .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                    &vk_mem::AllocationCreateInfo::default(),
+                )?;
+                ctx.set_name(*matrix_buffer, &format!("matrices {frame}"))?;
+                Ok(FrameResources {
+                    command_buffer,
+                    image_available: ctx.create_semaphore()?,
+                    global_set,
+                    matrix_buffer,
+                    done: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let exposure_buffer = Buffer::new(
+            &ctx,
+            &vk::BufferCreateInfo::default()
+                .size(size_of::<ExposureBuffer>() as u64)
+                .usage(vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            &vk_mem::AllocationCreateInfo::default(),
+        )?;
+        ctx.set_name(*exposure_buffer, "exposure")?;
+
+        // Rewritten only when the swapchain is resized.
+        write_post_set(device, post_set, *hdr_view, *hdr_sampler, *exposure_buffer);
+
+        // Upload startup data. This doesn't block; the first frame waits for it on the GPU.
+        let placeholder = GpuTexture::new(
+            &ctx,
+            &mut uploader.queue,
+            PLACEHOLDER_TEXTURE,
+            Texture::checkerboard(64, 8),
+        )?;
+        ctx.set_name(*placeholder.image, "placeholder texture")?;
+        uploader.submit_all(|staging| {
+            staging.stage_buffer(
+                *exposure_buffer,
+                0,
+                std::iter::once(ExposureBuffer {
+                    histogram: [0; 256],
+                    exposure: 1.0,
+                }),
+            )
+        })?;
+
+        Ok(Self {
+            frame: 0,
+            frame_timeline: ctx.create_timeline_semaphore(0)?,
+            frame_value: 0,
+            gpu_timer: GpuTimer::new(&ctx, frames)?,
+            last_render: None,
+            frames: frame_resources,
+            _command_pool: command_pool,
+            post_set,
+            _descriptor_pool: descriptor_pool,
+            placeholder,
+            ready: HashSet::new(),
+            uploader,
+            readback_buffer,
+            frame_capture,
+            exposure_readback: None,
+            exposure_buffer,
+            texture_sampler,
+            pipeline_cache,
+            pipeline_cache_path: config.pipeline_cache,
+            scene_pipelines,
+            pipeline_layout,
+            scene_constant_ranges,
+            _global_set_layout: global_set_layout,
+            vert_shader,
+            frag_shader,
+            tonemap_pipelines,
+            tonemap_vert_shader,
+            tonemap_frag_shader,
+            histogram_pipeline,
+            exposure_pipeline,
+            post_pipeline_layout,
+            post_constant_ranges,
+            _post_set_layout: post_set_layout,
+            supported_samples,
+            samples,
+            transients: TransientImages::new(&ctx),
+            hdr_sampler,
+            hdr_view,
+            hdr_image,
+            swapchain,
+            _leak_check: leak_check,
+            ctx,
+        })
+    }
+
+    /// Blocks until the GPU is done with every submitted frame, e.g. before dropping assets.
+    pub unsafe fn wait_idle(&self) -> Result<()> {
+        self.ctx.device.device_wait_idle().check("vkDeviceWaitIdle")
+    }
+
+    /// Recreates the swapchain after [`render`](Self::render) failed with
+    /// [`Error::OutOfDate`](crate::Error::OutOfDate). `extent` is the window's current size.
+    pub unsafe fn recreate_swapchain(&mut self, extent: vk::Extent2D) -> Result<()> {
+        self.wait_idle()?;
+        // Transient attachments are sized to the swapchain.
+        self.transients.clear();
+        let old_extent = self.swapchain.extent;
+        self.swapchain.recreate(&self.ctx, extent)?;
+        let extent = self.swapchain.extent;
+        if extent == old_extent {
+            return Ok(());
+        }
+
+        // So are the HDR target and frame captures.
+        let (hdr_image, hdr_view) = create_hdr_target(&self.ctx, extent)?;
+        write_post_set(
+            &self.ctx.device,
+            self.post_set,
+            *hdr_view,
+            *self.hdr_sampler,
+            *self.exposure_buffer,
+        );
+        self.hdr_view = hdr_view;
+        self.hdr_image = hdr_image;
+        if let Some(readback) = self.exposure_readback.take() {
+            self.readback_buffer.release(&self.ctx.device, readback)?;
+        }
+        self.readback_buffer = create_readback_buffer(&self.ctx, extent)?;
+        self.frame_capture = FrameCapture::new(extent, self.swapchain.format.format);
+        Ok(())
+    }
+
+    pub fn context(&self) -> &Rc<Context> {
+        &self.ctx
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.swapchain.extent
+    }
+
+    /// Fails with [`Error::UnsupportedFormat`] if frames can't be captured from the swapchain's
+    /// format, in which case [`Frame::capture`] is ignored.
+    pub fn check_capture(&self) -> Result<()> {
+        let format = self.swapchain.format.format;
+        match FrameCapture::supports(format) {
+            true => Ok(()),
+            false => Err(Error::UnsupportedFormat(format)),
+        }
+    }
+
+    /// Whether `asset`'s upload has finished, so frames draw it rather than a placeholder.
+    pub fn is_resident(&self, asset: AssetId) -> bool {
+        self.ready.contains(&asset)
+    }
+
+    /// Creates the buffers for `mesh`, which are streamed in over the next frames.
+    pub unsafe fn create_mesh(&mut self, asset: AssetId, mesh: &tobj::Mesh) -> Result<GpuMesh> {
+        GpuMesh::new(&self.ctx, &mut self.uploader.queue, asset, mesh)
+    }
+
+    /// Creates the image for `texture`, which is streamed in over the next frames.
+    pub unsafe fn create_texture(
+        &mut self,
+        asset: AssetId,
+        texture: Texture,
+    ) -> Result<GpuTexture> {
+        GpuTexture::new(&self.ctx, &mut self.uploader.queue, asset, texture)
+    }
+
+    /// Whether `desc` can be drawn with on this device.
+    pub fn supports_pipeline(&self, desc: &PipelineDesc) -> bool {
+        self.scene_pipelines.supports(desc)
+    }
+
+    /// Rebuilds the scene pipelines and attachments if the MSAA sample count changed.
+    pub unsafe fn set_msaa_samples(&mut self, requested: u32) -> Result<()> {
+        let samples = clamp_samples(requested, self.supported_samples);
+        if samples == self.samples {
+            return Ok(());
+        }
+
+        self.wait_idle()?;
+        self.scene_pipelines.set_targets(scene_targets(samples));
+        self.samples = samples;
+        self.transients.clear();
+        println!("MSAA: {samples:?}");
+        Ok(())
+    }
+
+    /// Replaces the shader loaded from `name`, e.g. `shader.frag`, with `spirv` and rebuilds its
+    /// pipeline. Returns false if no graphics pipeline uses `name`. On error the old pipeline
+    /// stays in use.
+    pub unsafe fn reload_shader(&mut self, name: &str, spirv: &[u8]) -> Result<bool> {
+        // The old pipelines may still be in use by frames in flight, so only wait once the new
+        // ones are built.
+        match name {
+            "shader.vert" | "shader.frag" => {
+                let reflection = Reflection::new(name, spirv)?;
+                check_scene_shader(&reflection)?;
+                let shader = self.ctx.create_shader_module(spirv)?;
+                let pipelines = match name {
+                    "shader.vert" => self
+                        .scene_pipelines
+                        .with_vert_shader(*shader, &reflection)?,
+                    _ => self.scene_pipelines.with_frag_shader(*shader)?,
+                };
+                self.wait_idle()?;
+                self.scene_pipelines = pipelines;
+                match name {
+                    "shader.vert" => self.vert_shader = shader,
+                    _ => self.frag_shader = shader,
+                }
+            }
+            "tonemap.vert" | "tonemap.frag" => {
+                let reflection = Reflection::new(name, spirv)?;
+                check_post_shader(&reflection)?;
+                let shader = self.ctx.create_shader_module(spirv)?;
+                let pipelines = match name {
+                    "tonemap.vert" => self
+                        .tonemap_pipelines
+                        .with_vert_shader(*shader, &reflection)?,
+                    _ => self.tonemap_pipelines.with_frag_shader(*shader)?,
+                };
+                self.wait_idle()?;
+                self.tonemap_pipelines = pipelines;
+                match name {
+                    "tonemap.vert" => self.tonemap_vert_shader = shader,
+                    _ => self.tonemap_frag_shader = shader,
+                }
+            }
+            _ => return Ok(false),
+        }
+        println!("Reloaded {name}");
+        Ok(true)
+    }
+
+    /// Records, submits and presents a frame. Fails with
+    /// [`Error::OutOfDate`](crate::Error::OutOfDate) when the swapchain has to be recreated first.
+    pub unsafe fn render(&mut self, frame: &Frame) -> Result<FrameOutput> {
+        let ctx = self.ctx.clone();
+        let device = &ctx.device;
+        let mut output = FrameOutput::default();
+
+        let now = Instant::now();
+        output.cpu_frame = self.last_render.map(|start| CpuFrame {
+            start,
+            duration: now - start,
+        });
+        self.last_render = Some(now);
+
+        let index = self.frame;
+        self.frame = (self.frame + 1) % self.frames.len();
+        let image_available = *self.frames[index].image_available;
+        // Signaled once this frame's submission finishes.
+        let frame_done = SyncPoint {
+            semaphore: *self.frame_timeline,
+            value: self.frame_value + 1,
+        };
+
+        // Wait for the last frame that used this frame's resources.
+        SyncPoint {
+            semaphore: *self.frame_timeline,
+            value: self.frames[index].done,
+        }
+        .wait(device)?;
+        self.uploader.staging_buffer.reclaim(device)?;
+
+        // Stream a bounded chunk of pending uploads, if this frame's last chunk has landed.
+        self.uploader.stream(index, STREAM_BUDGET)?;
+
+        // Build the frame's pipelines up front. Failing once the image is acquired would leave
+        // it, and the semaphore signaled by acquiring it, for the error path to clean up.
+        self.scene_pipelines.get(&frame.pipeline)?;
+        self.tonemap_pipelines.get(&TONEMAP_PIPELINE)?;
+
+        let image_index = self.swapchain.acquire(&ctx, image_available)?;
+        let upload_wait = match self.record(frame, index, image_index, frame_done, &mut output) {
+            Ok(upload_wait) => upload_wait,
+            Err(err) => {
+                // Present a blank image instead, so the image, its semaphores and this frame's
+                // resources are released as if the frame had rendered.
+                self.record_blank(index, image_index)?;
+                self.submit(index, image_index, frame_done, None)?;
+                self.present(image_index)?;
+                return Err(err);
+            }
+        };
+        self.submit(index, image_index, frame_done, upload_wait)?;
+        // The uploads are owned by the graphics queue once the submission runs.
+        self.uploader.commit_acquire();
+        self.present(image_index)?;
+
+        output.capture = self
+            .frame_capture
+            .read_rgba(device, &mut self.readback_buffer)?;
+        Ok(output)
+    }
+
+    // Records the frame into its command buffer. Returns the upload timeline value the
+    // submission has to wait for.
+    unsafe fn record(
+        &mut self,
+        frame: &Frame,
+        index: usize,
+        image_index: u32,
+        frame_done: SyncPoint,
+        output: &mut FrameOutput,
+    ) -> Result<Option<u64>> {
+        let ctx = self.ctx.clone();
+        let device = &ctx.device;
+        let extent = self.swapchain.extent;
+        let command_buffer = self.frames[index].command_buffer;
+        let matrix_buffer = *self.frames[index].matrix_buffer;
+        // Offscreen images are neither acquired nor presented.
+        let present = !self.swapchain.is_headless();
+
+        // Reset and record.
+        device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .check("vkResetCommandBuffer")?;
+        device
+            .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+            .check("vkBeginCommandBuffer")?;
+
+        // This frame's last timestamps are ready now that its resources are free again.
+        if let Some(timer) = &mut self.gpu_timer {
+            output.gpu_timings = timer.begin_frame(command_buffer, index)?;
+        }
+
+        // Report the last exposure the GPU settled on.
+        if let Some(readback) = self.exposure_readback.take() {
+            if readback.is_ready(device)? {
+                output.exposure = Some(self.readback_buffer.read(device, &readback)?[0]);
+                self.readback_buffer.release(device, readback)?;
+            } else {
+                self.exposure_readback = Some(readback);
+            }
+        }
+
+        // Take ownership of freshly uploaded resources.
+        let (upload_wait, uploaded) = self.uploader.acquire(command_buffer)?;
+        self.ready.extend(uploaded);
+        let texture_view = match frame.texture {
+            Some(texture) if self.ready.contains(&texture.asset) => *texture.view,
+            _ => *self.placeholder.view,
+        };
+
+        // Declare the frame's passes; barriers and layouts follow from what each one touches.
+        let mut graph = RenderGraph::new();
+        let swapchain_image = graph.import_image(
+            "swapchain",
+            self.swapchain.images[image_index as usize],
+            *self.swapchain.views[image_index as usize],
+            vk::ImageAspectFlags::COLOR,
+            swapchain_state(present),
+        );
+        // Shared between frames, so the last frame's post reads must be done with it.
+        let hdr = graph.import_image(
+            "hdr color",
+            *self.hdr_image,
+            *self.hdr_view,
+            vk::ImageAspectFlags::COLOR,
+            ResourceState {
+                stages: vk::PipelineStageFlags2::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags2::COMPUTE_SHADER,
+                access: vk::AccessFlags2::empty(),
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        );
+        let exposure = graph.import_buffer(
+            *self.exposure_buffer,
+            ResourceState {
+                stages: vk::PipelineStageFlags2::COMPUTE_SHADER
+                    | vk::PipelineStageFlags2::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags2::TRANSFER,
+                access: vk::AccessFlags2::SHADER_WRITE,
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        );
+        // The last frame to use this frame's matrices has finished.
+        let matrices = graph.import_buffer(matrix_buffer, ResourceState::UNDEFINED);
+        let depth = graph.transient_image(
+            "depth",
+            TransientDesc {
+                format: DEPTH_FORMAT,
+                extent,
+                samples: self.samples,
+            },
+        );
+        // Resolved into the HDR image when multisampling.
+        let msaa = (self.samples != vk::SampleCountFlags::TYPE_1).then(|| {
+            graph.transient_image(
+                "msaa color",
+                TransientDesc {
+                    format: HDR_FORMAT,
+                    extent,
+                    samples: self.samples,
+                },
+            )
+        });
+
+        let upload_pass = graph
+            .pass("upload globals")
+            .buffer(matrices, BufferAccess::TransferDst)
+            .add();
+        let mut scene_pass = graph
+            .pass("scene")
+            .buffer(
+                matrices,
+                BufferAccess::Uniform(
+                    vk::PipelineStageFlags2::VERTEX_SHADER
+                        | vk::PipelineStageFlags2::FRAGMENT_SHADER,
+                ),
+            )
+            .image(depth, ImageAccess::DepthAttachment)
+            .image(hdr, ImageAccess::ColorAttachment);
+        if let Some(msaa) = msaa {
+            scene_pass = scene_pass.image(msaa, ImageAccess::ColorAttachment);
+        }
+        let scene_pass = scene_pass.add();
+        // Auto-exposure: build a luminance histogram, then reduce it to an exposure value.
+        let exposure_passes = frame.post.auto_exposure.then(|| {
+            let compute = vk::PipelineStageFlags2::COMPUTE_SHADER;
+            let histogram = graph
+                .pass("histogram")
+                .image(hdr, ImageAccess::Sampled(compute))
+                .buffer(exposure, BufferAccess::StorageReadWrite(compute))
+                .add();
+            let adapt = graph
+                .pass("exposure")
+                .buffer(exposure, BufferAccess::StorageReadWrite(compute))
+                .add();
+            // Read the adapted exposure back for display.
+            let readback = graph
+                .pass("exposure readback")
+                .buffer(exposure, BufferAccess::TransferSrc)
+                .add();
+            (histogram, adapt, readback)
+        });
+        let tonemap_pass = graph
+            .pass("tonemap")
+            .image(
+                hdr,
+                ImageAccess::Sampled(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+            )
+            .buffer(
+                exposure,
+                BufferAccess::StorageRead(vk::PipelineStageFlags2::FRAGMENT_SHADER),
+            )
+            .image(swapchain_image, ImageAccess::ColorAttachment)
+            .add();
+        let capture = frame.capture && FrameCapture::supports(self.swapchain.format.format);
+        let capture_pass = capture.then(|| {
+            graph
+                .pass("capture")
+                .image(swapchain_image, ImageAccess::TransferSrc)
+                .add()
+        });
+        // Nothing presents offscreen images, so they stay in their last layout.
+        if present {
+            graph.finish_image(swapchain_image, ImageAccess::Present);
+        }
+
+        let mut graph = graph.execute(
+            device,
+            command_buffer,
+            &mut self.transients,
+            self.gpu_timer.as_mut(),
+        )?;
+
+        // Upload global descriptor data.
+        graph.begin_pass(upload_pass);
+        let mut staging =
+            self.uploader
+                .staging_buffer
+                .begin_transfer(device, command_buffer, None)?;
+        staging.stage_buffer(
+            matrix_buffer,
+            0,
+            std::iter::once_with(|| GlobalDescriptorSet {
+                proj: Mat4::perspective_rh_gl(
+                    std::f32::consts::FRAC_PI_4,
+                    extent.width as f32 / extent.height as f32,
+                    0.01,
+                    10.0,
+                ),
+                view: frame.view,
+            }),
+        )?;
+        staging.finish(frame_done);
+
+        // Begin rendering.
+        graph.begin_pass(scene_pass);
+        device.cmd_begin_rendering(
+            command_buffer,
+            &vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                })
+                .layer_count(1)
+                .depth_attachment(
+                    &vk::RenderingAttachmentInfo::default()
+                        .image_view(graph.view(depth))
+                        .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .clear_value(vk::ClearValue {
+                            depth_stencil: vk::ClearDepthStencilValue {
+                                depth: 1.0,
+                                stencil: 0,
+                            },
+                        }),
+                )
+                .color_attachments(&[match msaa {
+                    // Resolve the multisampled image into the HDR image.
+                    Some(msaa) => vk::RenderingAttachmentInfo::default()
+                        .image_view(graph.view(msaa))
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                        .resolve_image_view(graph.view(hdr))
+                        .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+                    None => vk::RenderingAttachmentInfo::default()
+                        .image_view(graph.view(hdr))
+                        .store_op(vk::AttachmentStoreOp::STORE),
+                }
+                .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .clear_value(vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 1.0],
+                    },
+                })]),
+        );
+
+        // Begin draw calls.
+        {
+            let global_set = self.frames[index].global_set;
+            device.update_descriptor_sets(
+                &[
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(global_set)
+                        .dst_binding(0)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+                        .descriptor_count(1)
+                        .buffer_info(&[vk::DescriptorBufferInfo::default()
+                            .buffer(matrix_buffer)
+                            .offset(0)
+                            .range(vk::WHOLE_SIZE)]),
+                    vk::WriteDescriptorSet::default()
+                        .dst_set(global_set)
+                        .dst_binding(1)
+                        .dst_array_element(0)
+                        .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                        .descriptor_count(1)
+                        .image_info(&[vk::DescriptorImageInfo::default()
+                            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                            .image_view(texture_view)
+                            .sampler(*self.texture_sampler)]),
+                ],
+                &[],
+            );
+
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                *self.pipeline_layout,
+                0,
+                &[global_set],
+                &[],
+            );
+
+            push_constants(
+                device,
+                command_buffer,
+                *self.pipeline_layout,
+                &self.scene_constant_ranges,
+                &SceneConstants { model: frame.model },
+            );
+
+            self.scene_pipelines
+                .bind(command_buffer, &frame.pipeline, extent)?;
+            if let Some(mesh) = frame.mesh.filter(|mesh| self.ready.contains(&mesh.asset)) {
+                device.cmd_bind_index_buffer(
+                    command_buffer,
+                    *mesh.index_buffer,
+                    0,
+                    vk::IndexType::UINT32,
+                );
+                device.cmd_bind_vertex_buffers(
+                    command_buffer,
+                    0,
+                    &[*mesh.position_buffer, *mesh.uv_buffer],
+                    &[0, 0],
+                );
+                device.cmd_draw_indexed(command_buffer, mesh.index_count, 1, 0, 0, 0);
+            }
+        }
+
+        device.cmd_end_rendering(command_buffer);
+
+        let post_constants = PostConstants {
+            min_log_lum: -10.0,
+            log_lum_range: 12.0,
+            dt: frame.dt,
+            exposure_ev: frame.post.exposure_ev,
+            tonemap: frame.post.tonemap as u32,
+            auto_exposure: frame.post.auto_exposure as u32,
+            encode_srgb: self.swapchain.needs_srgb_encode() as u32,
+        };
+
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            *self.post_pipeline_layout,
+            0,
+            &[self.post_set],
+            &[],
+        );
+        push_constants(
+            device,
+            command_buffer,
+            *self.post_pipeline_layout,
+            &self.post_constant_ranges,
+            &post_constants,
+        );
+
+        if let Some((histogram, adapt, readback)) = exposure_passes {
+            graph.begin_pass(histogram);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.histogram_pipeline,
+            );
+            device.cmd_dispatch(
+                command_buffer,
+                extent.width.div_ceil(16),
+                extent.height.div_ceil(16),
+                1,
+            );
+
+            graph.begin_pass(adapt);
+            device.cmd_bind_pipeline(
+                command_buffer,
+                vk::PipelineBindPoint::COMPUTE,
+                *self.exposure_pipeline,
+            );
+            device.cmd_dispatch(command_buffer, 1, 1, 1);
+
+            graph.begin_pass(readback);
+            if self.exposure_readback.is_none() {
+                // A full readback buffer only delays the exposure until a later frame.
+                self.exposure_readback = match self.readback_buffer.read_buffer::<f32>(
+                    device,
+                    command_buffer,
+                    frame_done,
+                    *self.exposure_buffer,
+                    std::mem::offset_of!(ExposureBuffer, exposure) as u64,
+                    1,
+                ) {
+                    Ok(readback) => Some(readback),
+                    Err(Error::StagingFull { .. }) => None,
+                    Err(err) => return Err(err),
+                };
+            }
+        }
+
+        // Tonemap into the swapchain image.
+        graph.begin_pass(tonemap_pass);
+        device.cmd_begin_rendering(
+            command_buffer,
+            &vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent,
+                })
+                .layer_count(1)
+                .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                    .image_view(graph.view(swapchain_image))
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .store_op(vk::AttachmentStoreOp::STORE)]),
+        );
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            *self.post_pipeline_layout,
+            0,
+            &[self.post_set],
+            &[],
+        );
+        self.tonemap_pipelines
+            .bind(command_buffer, &TONEMAP_PIPELINE, extent)?;
+        device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        device.cmd_end_rendering(command_buffer);
+
+        // Copy out the swapchain image.
+        if let Some(capture_pass) = capture_pass {
+            graph.begin_pass(capture_pass);
+            // Without room for the copy, the frame goes uncaptured and the caller asks again.
+            match self.frame_capture.record(
+                device,
+                command_buffer,
+                frame_done,
+                &mut self.readback_buffer,
+                graph.image(swapchain_image),
+            ) {
+                Err(err @ Error::StagingFull { .. }) => println!("Could not capture frame: {err}"),
+                result => result?,
+            }
+        }
+
+        // Convert the swapchain image for presenting.
+        graph.finish(&mut self.transients);
+
+        device
+            .end_command_buffer(command_buffer)
+            .check("vkEndCommandBuffer")?;
+        Ok(upload_wait)
+    }
+
+    // Records a frame that only clears `image_index`, in place of one that failed to record.
+    unsafe fn record_blank(&mut self, index: usize, image_index: u32) -> Result<()> {
+        let device = &self.ctx.device;
+        let command_buffer = self.frames[index].command_buffer;
+        device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .check("vkResetCommandBuffer")?;
+        device
+            .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+            .check("vkBeginCommandBuffer")?;
+
+        let present = !self.swapchain.is_headless();
+        let mut graph = RenderGraph::new();
+        let swapchain_image = graph.import_image(
+            "swapchain",
+            self.swapchain.images[image_index as usize],
+            *self.swapchain.views[image_index as usize],
+            vk::ImageAspectFlags::COLOR,
+            swapchain_state(present),
+        );
+        let clear_pass = graph
+            .pass("clear")
+            .image(swapchain_image, ImageAccess::ColorAttachment)
+            .add();
+        if present {
+            graph.finish_image(swapchain_image, ImageAccess::Present);
+        }
+
+        let mut graph = graph.execute(device, command_buffer, &mut self.transients, None)?;
+        graph.begin_pass(clear_pass);
+        device.cmd_begin_rendering(
+            command_buffer,
+            &vk::RenderingInfo::default()
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: self.swapchain.extent,
+                })
+                .layer_count(1)
+                .color_attachments(&[vk::RenderingAttachmentInfo::default()
+                    .image_view(graph.view(swapchain_image))
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(vk::ClearValue {
+                        color: vk::ClearColorValue {
+                            float32: [0.0, 0.0, 0.0, 1.0],
+                        },
+                    })]),
+        );
+        device.cmd_end_rendering(command_buffer);
+        graph.finish(&mut self.transients);
+
+        device
+            .end_command_buffer(command_buffer)
+            .check("vkEndCommandBuffer")
+    }
+
+    // Submits the frame's command buffer, signaling `frame_done` once it finishes.
+    unsafe fn submit(
+        &mut self,
+        index: usize,
+        image_index: u32,
+        frame_done: SyncPoint,
+        upload_wait: Option<u64>,
+    ) -> Result<()> {
+        let device = &self.ctx.device;
+        let command_buffer = self.frames[index].command_buffer;
+        let image_available = *self.frames[index].image_available;
+        // Presenting may still wait on an image's semaphore after the frame that last rendered
+        // to it finished, so they are kept per image rather than per frame.
+        let render_finished = *self.swapchain.render_finished[image_index as usize];
+        // Offscreen images are neither acquired nor presented, so there's nothing to wait on or
+        // signal for them.
+        let present = !self.swapchain.is_headless();
+
+        // Execute command buffer.
+        let mut waits = Vec::new();
+        let mut signals = Vec::new();
+        if present {
+            waits.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(image_available)
+                    .stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT),
+            );
+            signals.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(render_finished)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            );
+        }
+        if let Some(value) = upload_wait {
+            waits.push(
+                vk::SemaphoreSubmitInfo::default()
+                    .semaphore(self.uploader.timeline())
+                    .value(value)
+                    .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+            );
+        }
+        signals.push(
+            vk::SemaphoreSubmitInfo::default()
+                .semaphore(frame_done.semaphore)
+                .value(frame_done.value)
+                .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS),
+        );
+        device
+            .queue_submit2(
+                self.ctx.queues.graphics,
+                &[vk::SubmitInfo2::default()
+                    .wait_semaphore_infos(&waits)
+                    .command_buffer_infos(&[
+                        vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)
+                    ])
+                    .signal_semaphore_infos(&signals)],
+                vk::Fence::null(),
+            )
+            .check("vkQueueSubmit2")?;
+        self.frame_value = frame_done.value;
+        self.frames[index].done = frame_done.value;
+        Ok(())
+    }
+
+    unsafe fn present(&self, image_index: u32) -> Result<()> {
+        let render_finished = *self.swapchain.render_finished[image_index as usize];
+        self.swapchain
+            .present(&self.ctx, image_index, render_finished)
+    }
+}
+
+// The linear HDR color target the scene is drawn into, and a view of it.
+unsafe fn create_hdr_target(ctx: &Rc<Context>, extent: vk::Extent2D) -> Result<(Image, ImageView)> {
+    let hdr_image = Image::new(
+        ctx,
+        &vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .extent(
+                vk::Extent3D::default()
+                    .width(extent.width)
+                    .height(extent.height)
+                    .depth(1),
+            )
+            .mip_levels(1)
+            .array_layers(1)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .format(HDR_FORMAT)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED),
+        &vk_mem::AllocationCreateInfo {
+            required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            ..Default::default()
+        },
+    )?;
+    ctx.set_name(*hdr_image, "hdr color")?;
+
+    let hdr_view = hdr_image.create_view(
+        vk::ImageViewType::TYPE_2D,
+        HDR_FORMAT,
+        vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    )?;
+    Ok((hdr_image, hdr_view))
+}
+
+// Points the post set at the HDR target and exposure buffer.
+unsafe fn write_post_set(
+    device: &ash::Device,
+    post_set: vk::DescriptorSet,
+    hdr_view: vk::ImageView,
+    hdr_sampler: vk::Sampler,
+    exposure_buffer: vk::Buffer,
+) {
+    device.update_descriptor_sets(
+        &[
+            vk::WriteDescriptorSet::default()
+                .dst_set(post_set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .descriptor_count(1)
+                .image_info(&[vk::DescriptorImageInfo::default()
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                    .image_view(hdr_view)
+                    .sampler(hdr_sampler)]),
+            vk::WriteDescriptorSet::default()
+                .dst_set(post_set)
+                .dst_binding(1)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                .descriptor_count(1)
+                .buffer_info(&[vk::DescriptorBufferInfo::default()
+                    .buffer(exposure_buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE)]),
+        ],
+        &[],
+    );
+}
+
+// Room for a few captured frames in flight.
+unsafe fn create_readback_buffer(
+    ctx: &Rc<Context>,
+    extent: vk::Extent2D,
+) -> Result<ReadbackBuffer> {
+    ReadbackBuffer::new(
+        ctx,
+        3 * extent.width as u64 * extent.height as u64 * 4,
+        &ctx.instance
+            .get_physical_device_properties(ctx.pdevice)
+            .limits,
+    )
+}
+
+// How an acquired swapchain image is first used. Acquiring waits on `image_available` at this
+// stage; offscreen images were last written by an earlier frame on this queue instead, and are
+// left in whatever layout that frame used.
+fn swapchain_state(present: bool) -> ResourceState {
+    match present {
+        true => ResourceState {
+            stages: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            access: vk::AccessFlags2::empty(),
+            layout: vk::ImageLayout::UNDEFINED,
+        },
+        false => ResourceState {
+            stages: vk::PipelineStageFlags2::ALL_COMMANDS,
+            access: vk::AccessFlags2::MEMORY_WRITE,
+            layout: vk::ImageLayout::UNDEFINED,
+        },
+    }
+}
+
+impl Drop for Renderer {
+    fn drop(&mut self) {
+        // Block until the gpu is finished; everything is destroyed as it goes out of scope.
+        let _ = unsafe { self.ctx.device.device_wait_idle() };
+        if let Some(path) = &self.pipeline_cache_path {
+            if let Err(err) = unsafe { pipeline_cache::save(&self.ctx, *self.pipeline_cache, path) }
+            {
+                println!("Could not save pipeline cache {}: {err}", path.display());
+            }
+        }
+    }
+}
@@ -1,187 +1,1040 @@
 use ash::vk;
 use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::rc::Rc;
 
+use crate::context::{Buffer, Context, SyncPoint};
+use crate::error::{Error, Result};
+
+/// A region of the ring still owned by a transfer. `done` is `None` until the transfer finishes.
+struct Region {
+    start: u64,
+    end: u64,
+    done: Option<SyncPoint>,
+    /// Kept past `done` until the host is done with it.
+    held: bool,
+}
+
+/// Timeline-tracked ring allocator over a buffer of `len` bytes.
+struct Ring {
+    len: u64,
+    regions: VecDeque<Region>,
+}
+
+impl Ring {
+    /// Releases the front regions whose submission has finished and which are no longer held.
+    unsafe fn reclaim(&mut self, device: &ash::Device) -> Result<()> {
+        while let Some(region) = self.regions.front() {
+            let finished = match region.done {
+                Some(done) => done.is_reached(device)?,
+                None => false,
+            };
+            if region.held || !finished {
+                break;
+            }
+            self.regions.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Reserves `size` contiguous bytes starting at a multiple of `align`, without touching any
+    /// region still in flight.
+    fn allocate(
+        &mut self,
+        size: u64,
+        align: u64,
+        done: Option<SyncPoint>,
+        held: bool,
+    ) -> Result<u64> {
+        // Whether the ring has wrapped is decided on the unaligned end; aligning may step past
+        // the front region.
+        let (head, tail, wrapped) = match (self.regions.front(), self.regions.back()) {
+            (Some(front), Some(back)) => (
+                back.end.next_multiple_of(align),
+                front.start,
+                back.end <= front.start,
+            ),
+            _ => (0, 0, false),
+        };
+
+        let start = if self.regions.is_empty() {
+            (size <= self.len).then_some(0)
+        } else if !wrapped {
+            // Free space is [head, len) followed by [0, tail).
+            if head + size <= self.len {
+                Some(head)
+            } else {
+                (size <= tail).then_some(0)
+            }
+        } else {
+            // Wrapped: free space is [head, tail).
+            (head + size <= tail).then_some(head)
+        };
+
+        let start = start.ok_or_else(|| Error::StagingFull {
+            requested: size,
+            available: if self.regions.is_empty() {
+                self.len
+            } else if !wrapped {
+                self.len.saturating_sub(head).max(tail)
+            } else {
+                tail.saturating_sub(head)
+            },
+        })?;
+
+        if size > 0 {
+            self.regions.push_back(Region {
+                start,
+                end: start + size,
+                done,
+                held,
+            });
+        }
+        Ok(start)
+    }
+}
+
+/// Persistently mapped upload buffer, allocated as a ring.
+///
+/// Each transfer is tagged with the timeline value signaled by the submission that reads it, and
+/// its bytes are only reused once that value is reached.
 pub struct StagingBuffer {
-    buffer: vk::Buffer,
-    alloc: vk_mem::Allocation,
+    buffer: Buffer,
     map: *mut u8,
+    /// `optimalBufferCopyOffsetAlignment` of the device.
+    copy_alignment: u64,
+    ring: Ring,
 }
 
 impl StagingBuffer {
-    pub unsafe fn new(len: u64, allocator: &vk_mem::Allocator) -> Self {
-        use vk_mem::Alloc;
-        let (buffer, mut alloc) = allocator
-            .create_buffer(
-                &vk::BufferCreateInfo::default()
-                    .size(len as u64)
-                    .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-                    .sharing_mode(vk::SharingMode::EXCLUSIVE),
-                &vk_mem::AllocationCreateInfo {
-                    flags: vk_mem::AllocationCreateFlags::MAPPED
-                        | vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
-                    usage: vk_mem::MemoryUsage::AutoPreferHost,
-                    required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
-                        | vk::MemoryPropertyFlags::HOST_COHERENT,
-                    ..Default::default()
-                },
-            )
-            .unwrap();
-
-        let map = allocator.map_memory(&mut alloc).unwrap();
-
-        Self { buffer, alloc, map }
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        len: u64,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> Result<Self> {
+        let mut buffer = Buffer::new(
+            ctx,
+            &vk::BufferCreateInfo::default()
+                .size(len)
+                .usage(vk::BufferUsageFlags::TRANSFER_SRC)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            &vk_mem::AllocationCreateInfo {
+                flags: vk_mem::AllocationCreateFlags::MAPPED
+                    | vk_mem::AllocationCreateFlags::HOST_ACCESS_SEQUENTIAL_WRITE,
+                usage: vk_mem::MemoryUsage::AutoPreferHost,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                    | vk::MemoryPropertyFlags::HOST_COHERENT,
+                ..Default::default()
+            },
+        )?;
+        let map = buffer.map()?;
+
+        Ok(Self {
+            buffer,
+            map,
+            copy_alignment: limits.optimal_buffer_copy_offset_alignment.max(1),
+            ring: Ring {
+                len,
+                regions: VecDeque::new(),
+            },
+        })
     }
 
+    /// Starts recording uploads into `command_buffer`. With a `transfer`, the staged resources
+    /// are released to another queue family and must be acquired there with the [`Acquires`]
+    /// returned by [`Staging::finish`].
     pub unsafe fn begin_transfer<'a>(
         &'a mut self,
         device: &'a ash::Device,
         command_buffer: vk::CommandBuffer,
-    ) -> Staging {
-        Staging {
+        transfer: Option<QueueTransfer>,
+    ) -> Result<Staging<'a>> {
+        self.reclaim(device)?;
+        Ok(Staging {
             device,
-            ptr: self.map,
             command_buffer,
             buffer: self,
-        }
+            transfer,
+            acquires: Acquires::default(),
+        })
     }
 
-    pub unsafe fn destroy(mut self, alloc: &vk_mem::Allocator) {
-        alloc.unmap_memory(&mut self.alloc);
-        alloc.destroy_buffer(self.buffer, &mut self.alloc);
-        std::mem::forget(self)
+    /// Releases the regions of every transfer whose submission has finished.
+    pub unsafe fn reclaim(&mut self, device: &ash::Device) -> Result<()> {
+        self.ring.reclaim(device)
     }
 }
 
-impl Drop for StagingBuffer {
-    fn drop(&mut self) {
-        println!(
-            "Warning: {} must be dropped with {}::destroy!",
-            std::any::type_name::<Self>(),
-            std::any::type_name::<Self>()
+/// Persistently mapped download buffer, allocated as a ring like [`StagingBuffer`].
+///
+/// Each copy returns a [`Readback`] handle. Its bytes stay reserved until the handle is given
+/// back with [`ReadbackBuffer::release`], after the host has read them.
+pub struct ReadbackBuffer {
+    buffer: Buffer,
+    map: *const u8,
+    /// `optimalBufferCopyOffsetAlignment` of the device.
+    copy_alignment: u64,
+    ring: Ring,
+}
+
+/// Copied data in a [`ReadbackBuffer`], readable once `done` is reached.
+#[must_use = "Readbacks must be released back to their ReadbackBuffer."]
+pub struct Readback<T> {
+    start: u64,
+    len: usize,
+    done: SyncPoint,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Readback<T> {
+    pub unsafe fn is_ready(&self, device: &ash::Device) -> Result<bool> {
+        self.done.is_reached(device)
+    }
+
+    pub unsafe fn wait(&self, device: &ash::Device) -> Result<()> {
+        self.done.wait(device)
+    }
+}
+
+impl ReadbackBuffer {
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        len: u64,
+        limits: &vk::PhysicalDeviceLimits,
+    ) -> Result<Self> {
+        let mut buffer = Buffer::new(
+            ctx,
+            &vk::BufferCreateInfo::default()
+                .size(len)
+                .usage(vk::BufferUsageFlags::TRANSFER_DST)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE),
+            &vk_mem::AllocationCreateInfo {
+                flags: vk_mem::AllocationCreateFlags::MAPPED
+                    | vk_mem::AllocationCreateFlags::HOST_ACCESS_RANDOM,
+                usage: vk_mem::MemoryUsage::AutoPreferHost,
+                required_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+                ..Default::default()
+            },
+        )?;
+        let map = buffer.map()?;
+
+        Ok(Self {
+            buffer,
+            map,
+            copy_alignment: limits.optimal_buffer_copy_offset_alignment.max(1),
+            ring: Ring {
+                len,
+                regions: VecDeque::new(),
+            },
+        })
+    }
+
+    unsafe fn allocate(
+        &mut self,
+        device: &ash::Device,
+        size: u64,
+        align: u64,
+        done: SyncPoint,
+    ) -> Result<u64> {
+        let align = lcm(align, self.copy_alignment);
+        self.ring.reclaim(device)?;
+        self.ring.allocate(size, align, Some(done), true)
+    }
+
+    /// Records a copy of `count` `T`s at byte `offset` of `src`. Writes to `src` must already be
+    /// visible to transfers; `done` must be signaled by the submission of `command_buffer`.
+    pub unsafe fn read_buffer<T: Copy>(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        done: SyncPoint,
+        src: vk::Buffer,
+        offset: u64,
+        count: usize,
+    ) -> Result<Readback<T>> {
+        let size = (count * size_of::<T>()) as u64;
+        let start = self.allocate(device, size, align_of::<T>() as u64, done)?;
+
+        device.cmd_copy_buffer(
+            command_buffer,
+            src,
+            *self.buffer,
+            &[vk::BufferCopy::default()
+                .src_offset(offset)
+                .dst_offset(start)
+                .size(size)],
+        );
+        self.make_host_visible(device, command_buffer);
+
+        Ok(Readback {
+            start,
+            len: count,
+            done,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Records a copy of mip level `mip`, layer `layer` of `src`, which must be in
+    /// `TRANSFER_SRC_OPTIMAL` with its writes visible to transfers. The texels are tightly
+    /// packed, so `T` is typically a texel block or a byte.
+    pub unsafe fn read_image<T: Copy>(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        done: SyncPoint,
+        src: &ImageTarget,
+        mip: u32,
+        layer: u32,
+    ) -> Result<Readback<T>> {
+        if mip >= src.mip_levels || layer >= src.array_layers {
+            return Err(Error::InvalidArgument("mip level or layer out of range"));
+        }
+
+        let block = TexelBlock::of(src.format).ok_or(Error::UnsupportedFormat(src.format))?;
+        let (width, height) = src.mip_extent(mip);
+        let size = block.size_of(width, height);
+        if !size.is_multiple_of(size_of::<T>() as u64) {
+            return Err(Error::InvalidArgument(
+                "image size is not a multiple of the readback type",
+            ));
+        }
+        let align = lcm(lcm(block.size as u64, 4), align_of::<T>() as u64);
+        let start = self.allocate(device, size, align, done)?;
+
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            src.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            *self.buffer,
+            &[vk::BufferImageCopy::default()
+                .buffer_offset(start)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(src.aspect_mask())
+                        .mip_level(mip)
+                        .base_array_layer(layer)
+                        .layer_count(1),
+                )
+                .image_extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })],
         );
+        self.make_host_visible(device, command_buffer);
+
+        Ok(Readback {
+            start,
+            len: (size / size_of::<T>() as u64) as usize,
+            done,
+            _marker: PhantomData,
+        })
+    }
+
+    unsafe fn make_host_visible(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        device.cmd_pipeline_barrier2(
+            command_buffer,
+            &vk::DependencyInfo::default().memory_barriers(&[vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ)]),
+        );
+    }
+
+    /// Waits for `readback` and returns a copy of its contents.
+    pub unsafe fn read<T: Copy>(
+        &self,
+        device: &ash::Device,
+        readback: &Readback<T>,
+    ) -> Result<Vec<T>> {
+        readback.wait(device)?;
+        self.buffer
+            .invalidate(readback.start, (readback.len * size_of::<T>()) as u64)?;
+        // The mapping's alignment isn't guaranteed to satisfy `T`.
+        let data = self.map.add(readback.start as usize) as *const T;
+        Ok((0..readback.len)
+            .map(|i| data.add(i).read_unaligned())
+            .collect())
+    }
+
+    /// Gives the bytes of `readback` back to the ring.
+    pub unsafe fn release<T>(&mut self, device: &ash::Device, readback: Readback<T>) -> Result<()> {
+        if let Some(region) = self
+            .ring
+            .regions
+            .iter_mut()
+            .find(|region| region.held && region.start == readback.start)
+        {
+            region.held = false;
+        }
+        self.ring.reclaim(device)
     }
 }
 
+/// An open transfer. Must be closed with [`Staging::finish`] before its command buffer is
+/// submitted; dropping it instead releases its regions.
 pub struct Staging<'a> {
     device: &'a ash::Device,
     command_buffer: vk::CommandBuffer,
-    ptr: *mut u8,
     buffer: &'a mut StagingBuffer,
+    transfer: Option<QueueTransfer>,
+    acquires: Acquires,
+}
+
+/// Queue family ownership transfer from the uploading queue to the one using the resources.
+#[derive(Copy, Clone, Debug)]
+pub struct QueueTransfer {
+    pub src_family: u32,
+    pub dst_family: u32,
+}
+
+/// Acquire barriers completing a [`QueueTransfer`], recorded on the destination queue after
+/// waiting for the upload.
+#[derive(Default)]
+pub struct Acquires {
+    buffers: Vec<vk::BufferMemoryBarrier2<'static>>,
+    images: Vec<vk::ImageMemoryBarrier2<'static>>,
+}
+
+impl Acquires {
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.images.is_empty()
+    }
+
+    pub unsafe fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if self.is_empty() {
+            return;
+        }
+
+        device.cmd_pipeline_barrier2(
+            command_buffer,
+            &vk::DependencyInfo::default()
+                .buffer_memory_barriers(&self.buffers)
+                .image_memory_barriers(&self.images),
+        );
+    }
 }
 
 impl<'a> Staging<'a> {
-    pub unsafe fn stage_buffer<T: Copy>(
-        mut self,
+    unsafe fn allocate(&mut self, size: u64, align: u64) -> Result<u64> {
+        let align = lcm(align, self.buffer.copy_alignment);
+        let ring = &mut self.buffer.ring;
+        ring.allocate(size, align, None, false).or_else(|_| {
+            ring.reclaim(self.device)?;
+            ring.allocate(size, align, None, false)
+        })
+    }
+
+    pub unsafe fn stage_buffer<T: Copy, I>(
+        &mut self,
         dst: vk::Buffer,
         offset: u64,
-        data: impl IntoIterator<Item = impl Borrow<T>>,
-    ) -> Self {
-        let start = self.ptr;
+        data: I,
+    ) -> Result<()>
+    where
+        I: IntoIterator<Item: Borrow<T>, IntoIter: ExactSizeIterator>,
+    {
+        let data = data.into_iter();
+        let size = (data.len() * std::mem::size_of::<T>()) as u64;
+        let start = self.allocate(size, std::mem::align_of::<T>() as u64)?;
+
+        // The mapping's alignment isn't guaranteed to satisfy `T`.
+        let mut ptr = self.buffer.map.add(start as usize);
         for t in data {
-            *(self.ptr as *mut T) = *t.borrow();
-            self.ptr = self.ptr.wrapping_add(std::mem::size_of::<T>());
+            ptr.cast::<T>().write_unaligned(*t.borrow());
+            ptr = ptr.add(std::mem::size_of::<T>());
         }
 
         self.device.cmd_copy_buffer(
             self.command_buffer,
-            self.buffer.buffer,
+            *self.buffer.buffer,
             dst,
             &[vk::BufferCopy::default()
-                .src_offset(start.byte_offset_from(self.buffer.map) as u64)
+                .src_offset(start)
                 .dst_offset(offset)
-                .size((self.ptr).byte_offset_from(start) as u64)],
+                .size(size)],
         );
 
-        self
+        if let Some(transfer) = self.transfer {
+            // The buffer's eventual use isn't known, so make it visible to everything.
+            self.acquires.buffers.push(
+                vk::BufferMemoryBarrier2::default()
+                    .buffer(dst)
+                    .offset(offset)
+                    .size(size)
+                    .src_queue_family_index(transfer.src_family)
+                    .dst_queue_family_index(transfer.dst_family)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_access_mask(vk::AccessFlags2::MEMORY_READ),
+            );
+        }
+
+        Ok(())
     }
 
+    /// Stages texel data for mip levels `mips` and array layers `layers` of `target`, leaving
+    /// those subresources in `final_layout`. Cube faces are array layers in +X, -X, +Y, -Y, +Z, -Z
+    /// order.
+    ///
+    /// `data` holds tightly packed texel blocks for each mip level in turn, and within a level for
+    /// each layer in turn. The subresources are fully overwritten, so their previous contents are
+    /// discarded.
     pub unsafe fn stage_image(
-        mut self,
-        image: vk::Image,
-        width: u32,
-        height: u32,
+        &mut self,
+        target: &ImageTarget,
+        mips: Range<u32>,
+        layers: Range<u32>,
+        final_layout: vk::ImageLayout,
         data: impl IntoIterator<Item = u8>,
-    ) -> Self {
-        let start = self.ptr;
-        for t in data {
-            *self.ptr = t;
-            self.ptr = self.ptr.add(1);
+    ) -> Result<()> {
+        if mips.is_empty() || mips.end > target.mip_levels {
+            return Err(Error::InvalidArgument("mip levels out of range"));
+        }
+        if layers.is_empty() || layers.end > target.array_layers {
+            return Err(Error::InvalidArgument("array layers out of range"));
         }
 
-        self.device.cmd_pipeline_barrier(
+        let block = TexelBlock::of(target.format).ok_or(Error::UnsupportedFormat(target.format))?;
+        // Buffer-image copy offsets must be a multiple of both the block size and 4.
+        let align = lcm(lcm(block.size as u64, 4), self.buffer.copy_alignment);
+        let layer_count = layers.end - layers.start;
+
+        // Lay out one aligned slot per mip level.
+        let mut slots = Vec::with_capacity(mips.len());
+        let mut size = 0_u64;
+        for mip in mips.clone() {
+            let (width, height) = target.mip_extent(mip);
+            let offset = size.next_multiple_of(align);
+            let len = block.size_of(width, height) * layer_count as u64;
+            slots.push((mip, offset, len));
+            size = offset + len;
+        }
+        let start = self.allocate(size, align)?;
+
+        let mut data = data.into_iter();
+        for &(_, offset, len) in &slots {
+            let slot = std::slice::from_raw_parts_mut(
+                self.buffer.map.add((start + offset) as usize),
+                len as usize,
+            );
+            let mut written = 0;
+            for (dst, t) in slot.iter_mut().zip(&mut data) {
+                *dst = t;
+                written += 1;
+            }
+            if written != slot.len() {
+                return Err(Error::InvalidArgument(
+                    "image data does not match its extent",
+                ));
+            }
+        }
+        if data.next().is_some() {
+            return Err(Error::InvalidArgument(
+                "image data does not match its extent",
+            ));
+        }
+
+        let aspect_mask = target.aspect_mask();
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(mips.start)
+            .level_count(mips.end - mips.start)
+            .base_array_layer(layers.start)
+            .layer_count(layer_count);
+
+        self.begin_image_write(target, subresource_range);
+
+        let copies = slots
+            .iter()
+            .map(|&(mip, offset, _)| {
+                let (width, height) = target.mip_extent(mip);
+                vk::BufferImageCopy::default()
+                    .buffer_offset(start + offset)
+                    .image_subresource(
+                        vk::ImageSubresourceLayers::default()
+                            .aspect_mask(aspect_mask)
+                            .mip_level(mip)
+                            .base_array_layer(layers.start)
+                            .layer_count(layer_count),
+                    )
+                    .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                    .image_extent(vk::Extent3D {
+                        width,
+                        height,
+                        depth: 1,
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        self.device.cmd_copy_buffer_to_image(
             self.command_buffer,
-            vk::PipelineStageFlags::TOP_OF_PIPE,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[vk::ImageMemoryBarrier::default()
-                .image(image)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .subresource_range(
-                    vk::ImageSubresourceRange::default()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .base_mip_level(0)
-                        .level_count(1)
-                        .base_array_layer(0)
-                        .layer_count(1),
-                )
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .src_access_mask(vk::AccessFlags::empty())
-                .dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)],
+            *self.buffer.buffer,
+            target.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &copies,
         );
 
+        self.end_image_write(target, subresource_range, final_layout);
+
+        Ok(())
+    }
+
+    /// Stages rows `rows` of mip level 0, layer 0 of `target`, for uploads split across several
+    /// transfers. The chunk starting at row 0 discards the previous contents, and the chunk ending
+    /// at the last row leaves the image in `final_layout`; chunks must be staged in order.
+    ///
+    /// `data` holds the tightly packed texel blocks of those rows.
+    pub unsafe fn stage_image_rows(
+        &mut self,
+        target: &ImageTarget,
+        rows: Range<u32>,
+        final_layout: vk::ImageLayout,
+        data: &[u8],
+    ) -> Result<()> {
+        if target.mip_levels != 1 || target.array_layers != 1 {
+            return Err(Error::InvalidArgument(
+                "row chunks need a single mip level and layer",
+            ));
+        }
+        if rows.is_empty() || rows.end > target.height {
+            return Err(Error::InvalidArgument("rows out of range"));
+        }
+
+        let block = TexelBlock::of(target.format).ok_or(Error::UnsupportedFormat(target.format))?;
+        if !rows.start.is_multiple_of(block.height)
+            || !(rows.end.is_multiple_of(block.height) || rows.end == target.height)
+        {
+            return Err(Error::InvalidArgument(
+                "row chunks must be aligned to the texel block height",
+            ));
+        }
+        let align = lcm(lcm(block.size as u64, 4), self.buffer.copy_alignment);
+        let len = block.size_of(target.width, rows.end - rows.start);
+        if data.len() as u64 != len {
+            return Err(Error::InvalidArgument(
+                "image data does not match its extent",
+            ));
+        }
+
+        let start = self.allocate(len, align)?;
+        std::ptr::copy_nonoverlapping(
+            data.as_ptr(),
+            self.buffer.map.add(start as usize),
+            data.len(),
+        );
+
+        let aspect_mask = target.aspect_mask();
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(aspect_mask)
+            .base_mip_level(0)
+            .level_count(1)
+            .base_array_layer(0)
+            .layer_count(1);
+
+        if rows.start == 0 {
+            self.begin_image_write(target, subresource_range);
+        }
+
         self.device.cmd_copy_buffer_to_image(
             self.command_buffer,
-            self.buffer.buffer,
-            image,
+            *self.buffer.buffer,
+            target.image,
             vk::ImageLayout::TRANSFER_DST_OPTIMAL,
             &[vk::BufferImageCopy::default()
-                .buffer_offset(start.offset_from(self.buffer.map) as u64)
+                .buffer_offset(start)
                 .image_subresource(
                     vk::ImageSubresourceLayers::default()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .aspect_mask(aspect_mask)
                         .mip_level(0)
                         .base_array_layer(0)
                         .layer_count(1),
                 )
-                .image_offset(vk::Offset3D { x: 0, y: 0, z: 0 })
+                .image_offset(vk::Offset3D {
+                    x: 0,
+                    y: rows.start as i32,
+                    z: 0,
+                })
                 .image_extent(vk::Extent3D {
-                    width,
-                    height,
+                    width: target.width,
+                    height: rows.end - rows.start,
                     depth: 1,
                 })],
         );
 
-        self.device.cmd_pipeline_barrier(
+        if rows.end == target.height {
+            self.end_image_write(target, subresource_range, final_layout);
+        }
+
+        Ok(())
+    }
+
+    /// Moves the subresources from `UNDEFINED` to `TRANSFER_DST_OPTIMAL`.
+    unsafe fn begin_image_write(
+        &mut self,
+        target: &ImageTarget,
+        subresource_range: vk::ImageSubresourceRange,
+    ) {
+        self.device.cmd_pipeline_barrier2(
             self.command_buffer,
-            vk::PipelineStageFlags::TRANSFER,
-            vk::PipelineStageFlags::FRAGMENT_SHADER,
-            vk::DependencyFlags::empty(),
-            &[],
-            &[],
-            &[vk::ImageMemoryBarrier::default()
-                .image(image)
-                .old_layout(vk::ImageLayout::UNDEFINED)
-                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
-                .subresource_range(
-                    vk::ImageSubresourceRange::default()
-                        .aspect_mask(vk::ImageAspectFlags::COLOR)
-                        .base_mip_level(0)
-                        .level_count(1)
-                        .base_array_layer(0)
-                        .layer_count(1),
-                )
-                .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
-                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                .dst_access_mask(vk::AccessFlags::SHADER_READ)],
+            &vk::DependencyInfo::default().image_memory_barriers(&[
+                vk::ImageMemoryBarrier2::default()
+                    .image(target.image)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .subresource_range(subresource_range)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE),
+            ]),
         );
+    }
+
+    /// Moves the subresources from `TRANSFER_DST_OPTIMAL` to `final_layout`, releasing it to the
+    /// destination queue if this is a queue transfer.
+    unsafe fn end_image_write(
+        &mut self,
+        target: &ImageTarget,
+        subresource_range: vk::ImageSubresourceRange,
+        final_layout: vk::ImageLayout,
+    ) {
+        let (dst_stage, dst_access) = layout_usage(final_layout);
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .image(target.image)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(final_layout)
+            .subresource_range(subresource_range);
 
-        self
+        match self.transfer {
+            // Release; the layout transition happens as part of the ownership transfer.
+            Some(transfer) => {
+                let barrier = barrier
+                    .src_queue_family_index(transfer.src_family)
+                    .dst_queue_family_index(transfer.dst_family);
+                self.device.cmd_pipeline_barrier2(
+                    self.command_buffer,
+                    &vk::DependencyInfo::default().image_memory_barriers(&[barrier
+                        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)]),
+                );
+                self.acquires.images.push(
+                    barrier
+                        .dst_stage_mask(dst_stage)
+                        .dst_access_mask(dst_access),
+                );
+            }
+            None => self.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().image_memory_barriers(&[barrier
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(dst_stage)
+                    .dst_access_mask(dst_access)]),
+            ),
+        }
     }
 
-    pub unsafe fn finish(self) {}
+    /// Closes the transfer. `done` must be signaled by the submission of this command buffer;
+    /// the staged bytes are not reused until it is reached.
+    pub unsafe fn finish(mut self, done: SyncPoint) -> Acquires {
+        for region in self.buffer.ring.regions.iter_mut().rev() {
+            if region.done.is_some() {
+                break;
+            }
+            region.done = Some(done);
+        }
+
+        // Release the staged buffers.
+        if !self.acquires.buffers.is_empty() {
+            let releases = self
+                .acquires
+                .buffers
+                .iter()
+                .map(|&barrier| {
+                    barrier
+                        .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                        .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                        .dst_stage_mask(vk::PipelineStageFlags2::NONE)
+                        .dst_access_mask(vk::AccessFlags2::NONE)
+                })
+                .collect::<Vec<_>>();
+            self.device.cmd_pipeline_barrier2(
+                self.command_buffer,
+                &vk::DependencyInfo::default().buffer_memory_barriers(&releases),
+            );
+        }
+
+        let acquires = std::mem::take(&mut self.acquires);
+        std::mem::forget(self);
+        acquires
+    }
+}
+
+impl Drop for Staging<'_> {
+    fn drop(&mut self) {
+        while self
+            .buffer
+            .ring
+            .regions
+            .back()
+            .is_some_and(|region| region.done.is_none())
+        {
+            self.buffer.ring.regions.pop_back();
+        }
+    }
+}
+
+/// The image a [`Staging::stage_image`] call uploads into, or a
+/// [`ReadbackBuffer::read_image`] call copies from.
+#[derive(Copy, Clone, Debug)]
+pub struct ImageTarget {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+}
+
+impl ImageTarget {
+    pub fn mip_extent(&self, mip: u32) -> (u32, u32) {
+        ((self.width >> mip).max(1), (self.height >> mip).max(1))
+    }
+
+    fn aspect_mask(&self) -> vk::ImageAspectFlags {
+        match self.format {
+            vk::Format::D16_UNORM | vk::Format::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+            _ => vk::ImageAspectFlags::COLOR,
+        }
+    }
+}
+
+/// The stages and accesses an image in `layout` is typically used with.
+fn layout_usage(layout: vk::ImageLayout) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+    match layout {
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => (
+            vk::PipelineStageFlags2::VERTEX_SHADER
+                | vk::PipelineStageFlags2::FRAGMENT_SHADER
+                | vk::PipelineStageFlags2::COMPUTE_SHADER,
+            vk::AccessFlags2::SHADER_READ,
+        ),
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL => (
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+        ),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL => (
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => (
+            vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+        ),
+        _ => (
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        ),
+    }
+}
+
+/// Size in bytes and extent in texels of the smallest addressable unit of a format.
+#[derive(Copy, Clone, Debug)]
+pub struct TexelBlock {
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TexelBlock {
+    pub fn of(format: vk::Format) -> Option<Self> {
+        use vk::Format as F;
+        let (size, width, height) = match format {
+            F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB => (1, 1, 1),
+            F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_UINT | F::R8G8_SINT | F::R8G8_SRGB => (2, 1, 1),
+            F::R16_UNORM | F::R16_SNORM | F::R16_UINT | F::R16_SINT | F::R16_SFLOAT => (2, 1, 1),
+            F::D16_UNORM => (2, 1, 1),
+            F::R8G8B8A8_UNORM
+            | F::R8G8B8A8_SNORM
+            | F::R8G8B8A8_UINT
+            | F::R8G8B8A8_SINT
+            | F::R8G8B8A8_SRGB
+            | F::B8G8R8A8_UNORM
+            | F::B8G8R8A8_SRGB
+            | F::A2B10G10R10_UNORM_PACK32
+            | F::B10G11R11_UFLOAT_PACK32
+            | F::E5B9G9R9_UFLOAT_PACK32
+            | F::R16G16_UNORM
+            | F::R16G16_SFLOAT
+            | F::R32_UINT
+            | F::R32_SINT
+            | F::R32_SFLOAT
+            | F::D32_SFLOAT => (4, 1, 1),
+            F::R16G16B16A16_UNORM
+            | F::R16G16B16A16_SFLOAT
+            | F::R32G32_UINT
+            | F::R32G32_SINT
+            | F::R32G32_SFLOAT => (8, 1, 1),
+            F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => (12, 1, 1),
+            F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => (16, 1, 1),
+            F::BC1_RGB_UNORM_BLOCK
+            | F::BC1_RGB_SRGB_BLOCK
+            | F::BC1_RGBA_UNORM_BLOCK
+            | F::BC1_RGBA_SRGB_BLOCK
+            | F::BC4_UNORM_BLOCK
+            | F::BC4_SNORM_BLOCK
+            | F::ETC2_R8G8B8_UNORM_BLOCK
+            | F::ETC2_R8G8B8_SRGB_BLOCK
+            | F::ETC2_R8G8B8A1_UNORM_BLOCK
+            | F::ETC2_R8G8B8A1_SRGB_BLOCK
+            | F::EAC_R11_UNORM_BLOCK
+            | F::EAC_R11_SNORM_BLOCK => (8, 4, 4),
+            F::BC2_UNORM_BLOCK
+            | F::BC2_SRGB_BLOCK
+            | F::BC3_UNORM_BLOCK
+            | F::BC3_SRGB_BLOCK
+            | F::BC5_UNORM_BLOCK
+            | F::BC5_SNORM_BLOCK
+            | F::BC6H_UFLOAT_BLOCK
+            | F::BC6H_SFLOAT_BLOCK
+            | F::BC7_UNORM_BLOCK
+            | F::BC7_SRGB_BLOCK
+            | F::ETC2_R8G8B8A8_UNORM_BLOCK
+            | F::ETC2_R8G8B8A8_SRGB_BLOCK
+            | F::EAC_R11G11_UNORM_BLOCK
+            | F::EAC_R11G11_SNORM_BLOCK
+            | F::ASTC_4X4_UNORM_BLOCK
+            | F::ASTC_4X4_SRGB_BLOCK => (16, 4, 4),
+            F::ASTC_8X8_UNORM_BLOCK | F::ASTC_8X8_SRGB_BLOCK => (16, 8, 8),
+            _ => return None,
+        };
+        Some(Self {
+            size,
+            width,
+            height,
+        })
+    }
+
+    /// Bytes needed for a `width` x `height` region, rounded up to whole blocks.
+    pub fn size_of(&self, width: u32, height: u32) -> u64 {
+        width.div_ceil(self.width) as u64 * height.div_ceil(self.height) as u64 * self.size as u64
+    }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let gcd = |mut a: u64, mut b: u64| {
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    };
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(len: u64) -> Ring {
+        Ring {
+            len,
+            regions: VecDeque::new(),
+        }
+    }
+
+    fn allocate(ring: &mut Ring, size: u64, align: u64) -> Result<u64> {
+        let start = ring.allocate(size, align, None, false)?;
+        assert_eq!(start % align, 0, "misaligned start {start}");
+        // No two regions in flight may overlap.
+        for (i, a) in ring.regions.iter().enumerate() {
+            for b in ring.regions.iter().skip(i + 1) {
+                assert!(
+                    a.end <= b.start || b.end <= a.start,
+                    "{}..{} overlaps {}..{}",
+                    a.start,
+                    a.end,
+                    b.start,
+                    b.end
+                );
+            }
+        }
+        Ok(start)
+    }
+
+    fn is_full(result: Result<u64>) -> bool {
+        matches!(result, Err(Error::StagingFull { .. }))
+    }
+
+    #[test]
+    fn exact_fit() {
+        let mut ring = ring(16);
+        assert_eq!(allocate(&mut ring, 16, 1).unwrap(), 0);
+        assert!(is_full(allocate(&mut ring, 1, 1)));
+    }
+
+    #[test]
+    fn full() {
+        let mut ring = ring(16);
+        assert!(is_full(allocate(&mut ring, 17, 1)));
+        assert_eq!(allocate(&mut ring, 8, 1).unwrap(), 0);
+        assert_eq!(allocate(&mut ring, 8, 1).unwrap(), 8);
+        assert!(matches!(
+            allocate(&mut ring, 1, 1),
+            Err(Error::StagingFull {
+                requested: 1,
+                available: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn wrap_around() {
+        let mut ring = ring(16);
+        assert_eq!(allocate(&mut ring, 8, 1).unwrap(), 0);
+        assert_eq!(allocate(&mut ring, 6, 1).unwrap(), 8);
+        ring.regions.pop_front();
+
+        // Doesn't fit after the back region, so wraps to the start.
+        assert_eq!(allocate(&mut ring, 4, 1).unwrap(), 0);
+        // Exactly fills the gap before the front region.
+        assert_eq!(allocate(&mut ring, 4, 1).unwrap(), 4);
+        assert!(is_full(allocate(&mut ring, 1, 1)));
+
+        // Freeing the front region opens up the end again.
+        ring.regions.pop_front();
+        assert_eq!(allocate(&mut ring, 8, 1).unwrap(), 8);
+    }
+
+    #[test]
+    fn mixed_alignments() {
+        let mut ring = ring(64);
+        assert_eq!(allocate(&mut ring, 12, 4).unwrap(), 0);
+        assert_eq!(allocate(&mut ring, 20, 4).unwrap(), 12);
+        assert_eq!(allocate(&mut ring, 30, 1).unwrap(), 32);
+        ring.regions.pop_front();
+        assert_eq!(allocate(&mut ring, 9, 1).unwrap(), 0);
+
+        // Aligning the wrapped head to 16 steps past the front region at 12.
+        assert!(is_full(allocate(&mut ring, 4, 16)));
+        // Unaligned data still fits in the gap.
+        assert_eq!(allocate(&mut ring, 3, 1).unwrap(), 9);
+
+        // Aligned allocations in an unwrapped ring skip the padding.
+        let mut ring = ring_with(64, &[(1, 1), (12, 16), (8, 8)]);
+        assert_eq!(ring.regions[1].start, 16);
+        assert_eq!(ring.regions[2].start, 32);
+        assert_eq!(allocate(&mut ring, 4, 4).unwrap(), 40);
+    }
+
+    fn ring_with(len: u64, allocations: &[(u64, u64)]) -> Ring {
+        let mut ring = ring(len);
+        for &(size, align) in allocations {
+            allocate(&mut ring, size, align).unwrap();
+        }
+        ring
+    }
 }
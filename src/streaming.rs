@@ -0,0 +1,452 @@
+use ash::vk;
+use std::collections::VecDeque;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::context::{Buffer, Context, Image, ImageView};
+use crate::error::{Error, Result};
+use crate::staging::{ImageTarget, Staging};
+
+pub type AssetId = usize;
+
+#[derive(Copy, Clone, Debug)]
+pub enum AssetKind {
+    Texture,
+    Mesh,
+}
+
+/// A decoded asset, ready to be uploaded.
+pub enum Asset {
+    Texture(Texture),
+    Mesh(tobj::Mesh),
+}
+
+/// Tightly packed RGBA8 texels.
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl Texture {
+    /// A two-tone checkerboard, shown while the real texture streams in.
+    pub fn checkerboard(size: u32, cell: u32) -> Self {
+        let rgba = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x / cell + y / cell).is_multiple_of(2)))
+            .flat_map(|light| {
+                if light {
+                    [200, 200, 200, 255]
+                } else {
+                    [255, 0, 255, 255]
+                }
+            })
+            .collect();
+        Self {
+            width: size,
+            height: size,
+            rgba,
+        }
+    }
+}
+
+struct Job {
+    id: AssetId,
+    kind: AssetKind,
+    source: Source,
+}
+
+enum Source {
+    Embedded(&'static [u8]),
+    // Read on the worker, so slow disks don't stall the frame.
+    File(PathBuf),
+}
+
+/// Thread pool decoding assets off the main thread.
+pub struct Loader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(AssetId, Result<Asset>)>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Loader {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, job_receiver) = std::sync::mpsc::channel::<Job>();
+        let (result_sender, results) = std::sync::mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..threads.max(1))
+            .map(|i| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                std::thread::Builder::new()
+                    .name(format!("loader-{i}"))
+                    .spawn(move || loop {
+                        // Hold the lock only while waiting, not while decoding.
+                        let job = jobs.lock().unwrap().recv();
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        let asset = match job.source {
+                            Source::Embedded(data) => decode(job.kind, data),
+                            Source::File(path) => std::fs::read(path)
+                                .map_err(Error::from)
+                                .and_then(|data| decode(job.kind, &data)),
+                        };
+                        if results.send((job.id, asset)).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Could not spawn loader thread.")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            workers,
+        }
+    }
+
+    pub fn load(&self, id: AssetId, kind: AssetKind, data: &'static [u8]) {
+        self.send(Job {
+            id,
+            kind,
+            source: Source::Embedded(data),
+        });
+    }
+
+    /// Like `load`, but reads the asset from disk on a worker thread.
+    pub fn load_file(&self, id: AssetId, kind: AssetKind, path: PathBuf) {
+        self.send(Job {
+            id,
+            kind,
+            source: Source::File(path),
+        });
+    }
+
+    fn send(&self, job: Job) {
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+    }
+
+    /// Returns a finished asset, if any, without blocking.
+    pub fn poll(&self) -> Option<(AssetId, Result<Asset>)> {
+        self.results.try_recv().ok()
+    }
+}
+
+impl Drop for Loader {
+    fn drop(&mut self) {
+        // Closing the job channel stops the workers once they finish their current job.
+        drop(self.jobs.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn decode(kind: AssetKind, data: &[u8]) -> Result<Asset> {
+    match kind {
+        AssetKind::Texture => decode_png(data).map(Asset::Texture),
+        AssetKind::Mesh => {
+            let (models, _) = tobj::load_obj_buf(&mut BufReader::new(data), |_| {
+                Err(tobj::LoadError::OpenFileFailed)
+            })?;
+            models
+                .into_iter()
+                .next()
+                .map(|model| Asset::Mesh(model.mesh))
+                .ok_or(Error::EmptyAsset("OBJ has no models"))
+        }
+    }
+}
+
+fn decode_png(data: &[u8]) -> Result<Texture> {
+    let mut decoder = png::Decoder::new(data);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+
+    let rgba = match info.color_type {
+        png::ColorType::Rgba => buf,
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|px| [px[0], px[1], px[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|px| [px[0], px[0], px[0], px[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&px| [px, px, px, 255]).collect(),
+        png::ColorType::Indexed => unreachable!("Palettes are expanded by the decoder."),
+    };
+
+    Ok(Texture {
+        width: info.width,
+        height: info.height,
+        rgba,
+    })
+}
+
+enum UploadTarget {
+    Buffer(vk::Buffer),
+    /// Uploaded by rows into mip 0, layer 0, finishing in `SHADER_READ_ONLY_OPTIMAL`.
+    Image(ImageTarget),
+}
+
+struct Upload {
+    asset: AssetId,
+    target: UploadTarget,
+    data: Vec<u8>,
+    /// Bytes staged so far.
+    cursor: usize,
+}
+
+/// Uploads waiting for staging space, split into bounded chunks so no single frame stalls on a
+/// large asset.
+#[derive(Default)]
+pub struct UploadQueue {
+    uploads: VecDeque<Upload>,
+}
+
+impl UploadQueue {
+    pub fn is_empty(&self) -> bool {
+        self.uploads.is_empty()
+    }
+
+    /// Queues `data` to be copied to the start of `buffer`.
+    pub fn push_buffer(&mut self, asset: AssetId, buffer: vk::Buffer, data: Vec<u8>) {
+        self.uploads.push_back(Upload {
+            asset,
+            target: UploadTarget::Buffer(buffer),
+            data,
+            cursor: 0,
+        });
+    }
+
+    /// Queues tightly packed texel data for `target`, which must have a single subresource.
+    pub fn push_image(&mut self, asset: AssetId, target: ImageTarget, data: Vec<u8>) {
+        self.uploads.push_back(Upload {
+            asset,
+            target: UploadTarget::Image(target),
+            data,
+            cursor: 0,
+        });
+    }
+
+    /// Stages up to roughly `budget` bytes of queued uploads, in order. Returns the bytes staged
+    /// and the assets whose last chunk was staged; they are usable once this transfer completes.
+    pub unsafe fn record(
+        &mut self,
+        staging: &mut Staging,
+        budget: u64,
+    ) -> Result<(u64, Vec<AssetId>)> {
+        let mut staged = 0_u64;
+        let mut completed = Vec::new();
+
+        while let Some(upload) = self.uploads.front_mut() {
+            let left = budget.saturating_sub(staged) as usize;
+            let remaining = upload.data.len() - upload.cursor;
+
+            let result = match upload.target {
+                UploadTarget::Buffer(buffer) => {
+                    let len = remaining.min(left);
+                    if len == 0 {
+                        break;
+                    }
+                    let chunk = &upload.data[upload.cursor..upload.cursor + len];
+                    staging
+                        .stage_buffer::<u8, _>(buffer, upload.cursor as u64, chunk)
+                        .map(|()| len)
+                }
+                UploadTarget::Image(target) => {
+                    let row_len = upload.data.len() / target.height as usize;
+                    let first_row = (upload.cursor / row_len) as u32;
+                    // Always make progress, even if a single row is over budget.
+                    let rows = if staged == 0 {
+                        (left / row_len).max(1)
+                    } else {
+                        left / row_len
+                    };
+                    let last_row = (first_row + rows as u32).min(target.height);
+                    if last_row == first_row {
+                        break;
+                    }
+                    let len = (last_row - first_row) as usize * row_len;
+                    if len == upload.data.len() {
+                        // Fits in one go.
+                        staging
+                            .stage_image(
+                                &target,
+                                0..1,
+                                0..1,
+                                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                upload.data.iter().copied(),
+                            )
+                            .map(|()| len)
+                    } else {
+                        staging
+                            .stage_image_rows(
+                                &target,
+                                first_row..last_row,
+                                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                                &upload.data[upload.cursor..upload.cursor + len],
+                            )
+                            .map(|()| len)
+                    }
+                }
+            };
+
+            match result {
+                Ok(len) => {
+                    upload.cursor += len;
+                    staged += len as u64;
+                }
+                // Out of staging space; continue once earlier transfers retire.
+                Err(Error::StagingFull { .. }) => break,
+                Err(err) => return Err(err),
+            }
+
+            if upload.cursor == upload.data.len() {
+                let asset = self.uploads.pop_front().unwrap().asset;
+                if self.uploads.front().is_none_or(|next| next.asset != asset) {
+                    completed.push(asset);
+                }
+            }
+        }
+
+        Ok((staged, completed))
+    }
+}
+
+/// Index, position and texcoord buffers of a streamed mesh.
+pub struct GpuMesh {
+    pub asset: AssetId,
+    pub index_buffer: Buffer,
+    pub position_buffer: Buffer,
+    pub uv_buffer: Buffer,
+    pub index_count: u32,
+}
+
+impl GpuMesh {
+    /// Creates the buffers for `mesh` and queues their contents on `uploads`.
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        uploads: &mut UploadQueue,
+        asset: AssetId,
+        mesh: &tobj::Mesh,
+    ) -> Result<Self> {
+        let mut create_buffer =
+            |name: &str, usage: vk::BufferUsageFlags, data: Vec<u8>| -> Result<Buffer> {
+                let buffer = Buffer::new(
+                    ctx,
+                    &vk::BufferCreateInfo::default()
+                        .size(data.len() as u64)
+                        .usage(usage | vk::BufferUsageFlags::TRANSFER_DST)
+                        .sharing_mode(vk::SharingMode::EXCLUSIVE),
+                    &vk_mem::AllocationCreateInfo::default(),
+                )?;
+                ctx.set_name(*buffer, &format!("mesh {asset} {name}"))?;
+                uploads.push_buffer(asset, *buffer, data);
+                Ok(buffer)
+            };
+
+        Ok(Self {
+            asset,
+            index_buffer: create_buffer(
+                "indices",
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                mesh.indices.iter().flat_map(|i| i.to_ne_bytes()).collect(),
+            )?,
+            position_buffer: create_buffer(
+                "positions",
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                mesh.positions
+                    .iter()
+                    .flat_map(|x| x.to_ne_bytes())
+                    .collect(),
+            )?,
+            uv_buffer: create_buffer(
+                "texcoords",
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                mesh.texcoords
+                    .iter()
+                    .flat_map(|x| x.to_ne_bytes())
+                    .collect(),
+            )?,
+            index_count: mesh.indices.len() as u32,
+        })
+    }
+}
+
+/// A sampled RGBA8 sRGB texture.
+pub struct GpuTexture {
+    pub asset: AssetId,
+    // Declared first so it is dropped before the image.
+    pub view: ImageView,
+    pub image: Image,
+}
+
+impl GpuTexture {
+    pub const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+    /// Creates the image for `texture` and queues its texels on `uploads`.
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        uploads: &mut UploadQueue,
+        asset: AssetId,
+        texture: Texture,
+    ) -> Result<Self> {
+        let image = Image::new(
+            ctx,
+            &vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(vk::Extent3D {
+                    width: texture.width,
+                    height: texture.height,
+                    depth: 1,
+                })
+                .mip_levels(1)
+                .array_layers(1)
+                .format(Self::FORMAT)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .samples(vk::SampleCountFlags::TYPE_1),
+            &vk_mem::AllocationCreateInfo::default(),
+        )?;
+        ctx.set_name(*image, &format!("texture {asset}"))?;
+
+        let view = image.create_view(
+            vk::ImageViewType::TYPE_2D,
+            Self::FORMAT,
+            vk::ImageSubresourceRange::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .base_mip_level(0)
+                .level_count(1)
+                .base_array_layer(0)
+                .layer_count(1),
+        )?;
+
+        uploads.push_image(
+            asset,
+            ImageTarget {
+                image: *image,
+                format: Self::FORMAT,
+                width: texture.width,
+                height: texture.height,
+                mip_levels: 1,
+                array_layers: 1,
+            },
+            texture.rgba,
+        );
+
+        Ok(Self { asset, view, image })
+    }
+}
@@ -0,0 +1,367 @@
+use ash::vk;
+use std::rc::Rc;
+
+use crate::capture::FrameCapture;
+use crate::context::{Context, Image, ImageView, Owned, Semaphore, SurfaceKHR, SwapchainKHR};
+use crate::error::{Check, Error, Result};
+
+// Images rendered to in turn when there is no window.
+const HEADLESS_IMAGES: usize = 3;
+const HEADLESS_FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+
+/// The window's swapchain, and a color view and render-finished semaphore for each of its images.
+///
+/// Without a window, offscreen images stand in for the swapchain's. They are handed out in turn
+/// and never presented.
+pub struct Swapchain {
+    /// Signaled when rendering to the image is done, for presenting to wait on.
+    pub render_finished: Vec<Semaphore>,
+    // Declared in drop order: views, then the images, then the swapchain, then its surface.
+    pub views: Vec<ImageView>,
+    _offscreen: Vec<Image>,
+    swapchain: Option<(SwapchainKHR, SurfaceKHR)>,
+    pub images: Vec<vk::Image>,
+    pub format: vk::SurfaceFormatKHR,
+    pub extent: vk::Extent2D,
+    vsync: bool,
+    // The offscreen image acquired last.
+    current: usize,
+}
+
+impl Swapchain {
+    /// Creates a swapchain for `surface`, or offscreen images without one. Without `vsync`, an
+    /// immediate or mailbox present mode is used when available.
+    ///
+    /// `extent` is the window size, used where the surface leaves the extent to the swapchain.
+    pub unsafe fn new(
+        ctx: &Rc<Context>,
+        surface: Option<SurfaceKHR>,
+        extent: vk::Extent2D,
+        vsync: bool,
+    ) -> Result<Self> {
+        let (swapchain, offscreen, format, extent, images, views) = match surface {
+            Some(surface) => {
+                let (swapchain, format, extent) =
+                    create_swapchain(ctx, *surface, extent, vsync, None)?;
+                let (images, views) = create_views(ctx, *swapchain, format.format)?;
+                (
+                    Some((swapchain, surface)),
+                    Vec::new(),
+                    format,
+                    extent,
+                    images,
+                    views,
+                )
+            }
+            None => {
+                let (offscreen, views) = create_offscreen(ctx, extent)?;
+                let format = vk::SurfaceFormatKHR {
+                    format: HEADLESS_FORMAT,
+                    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+                };
+                let images = offscreen.iter().map(|image| **image).collect();
+                (None, offscreen, format, extent, images, views)
+            }
+        };
+        Ok(Self {
+            render_finished: (0..images.len())
+                .map(|_| ctx.create_semaphore())
+                .collect::<Result<_>>()?,
+            views,
+            _offscreen: offscreen,
+            swapchain,
+            images,
+            format,
+            extent,
+            vsync,
+            current: 0,
+        })
+    }
+
+    /// Whether the images are offscreen rather than presented to a window.
+    pub fn is_headless(&self) -> bool {
+        self.swapchain.is_none()
+    }
+
+    /// Replaces an out of date swapchain with one matching the surface again. `extent` is the
+    /// window size, as for [`new`](Self::new); the extent actually used is stored in `extent`.
+    ///
+    /// Does nothing while the surface has no area, e.g. while the window is minimized.
+    pub unsafe fn recreate(&mut self, ctx: &Rc<Context>, extent: vk::Extent2D) -> Result<()> {
+        // Offscreen images never go out of date.
+        let Some((old_swapchain, surface)) = &self.swapchain else {
+            return Ok(());
+        };
+        let capabilities = ctx
+            .surface_instance
+            .get_physical_device_surface_capabilities(ctx.pdevice, **surface)
+            .check("vkGetPhysicalDeviceSurfaceCapabilitiesKHR")?;
+        let extent = swapchain_extent(&capabilities, extent);
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(());
+        }
+        let (swapchain, format, extent) =
+            create_swapchain(ctx, **surface, extent, self.vsync, Some(**old_swapchain))?;
+        // The new images are only usable with pipelines built for the same format.
+        if format != self.format {
+            return Err(Error::UnsupportedFormat(format.format));
+        }
+        let (images, views) = create_views(ctx, *swapchain, format.format)?;
+        // A pending present may still wait on the old semaphores, so keep them.
+        while self.render_finished.len() < images.len() {
+            self.render_finished.push(ctx.create_semaphore()?);
+        }
+        self.views = views;
+        if let Some((old_swapchain, _)) = &mut self.swapchain {
+            *old_swapchain = swapchain;
+        }
+        self.images = images;
+        self.extent = extent;
+        Ok(())
+    }
+
+    /// Whether the tonemap pass has to encode sRGB itself.
+    pub fn needs_srgb_encode(&self) -> bool {
+        !matches!(
+            self.format.format,
+            vk::Format::B8G8R8A8_SRGB
+                | vk::Format::R8G8B8A8_SRGB
+                | vk::Format::A8B8G8R8_SRGB_PACK32
+        )
+    }
+
+    /// Acquires the next image, signalling `image_available` once it can be written. Fails
+    /// with [`Error::OutOfDate`] when the swapchain has to be recreated.
+    ///
+    /// Offscreen images are returned in turn without signalling anything; they can be written
+    /// once the commands submitted earlier are done with them.
+    pub unsafe fn acquire(&mut self, ctx: &Context, image_available: vk::Semaphore) -> Result<u32> {
+        let Some((swapchain, _)) = &self.swapchain else {
+            self.current = (self.current + 1) % self.images.len();
+            return Ok(self.current as u32);
+        };
+        let (index, _) = ctx
+            .swapchain_device
+            .acquire_next_image(**swapchain, u64::MAX, image_available, vk::Fence::null())
+            .check("vkAcquireNextImageKHR")?;
+        Ok(index)
+    }
+
+    /// Presents image `index` once `wait` is signalled. Does nothing for offscreen images.
+    pub unsafe fn present(&self, ctx: &Context, index: u32, wait: vk::Semaphore) -> Result<()> {
+        let Some((swapchain, _)) = &self.swapchain else {
+            return Ok(());
+        };
+        let waits = [wait];
+        let swapchains = [**swapchain];
+        let images = [index];
+        let present_info = vk::PresentInfoKHR::default()
+            .wait_semaphores(&waits)
+            .swapchains(&swapchains)
+            .image_indices(&images);
+        ctx.swapchain_device
+            .queue_present(ctx.queues.present, &present_info)
+            .check("vkQueuePresentKHR")?;
+        Ok(())
+    }
+}
+
+unsafe fn create_swapchain(
+    ctx: &Rc<Context>,
+    surface: vk::SurfaceKHR,
+    extent: vk::Extent2D,
+    vsync: bool,
+    old_swapchain: Option<vk::SwapchainKHR>,
+) -> Result<(SwapchainKHR, vk::SurfaceFormatKHR, vk::Extent2D)> {
+    let surface_instance = &ctx.surface_instance;
+    // Prefer a format frames can be captured in.
+    let formats = surface_instance
+        .get_physical_device_surface_formats(ctx.pdevice, surface)
+        .check("vkGetPhysicalDeviceSurfaceFormatsKHR")?;
+    let format = formats
+        .iter()
+        .find(|format| FrameCapture::supports(format.format))
+        .or(formats.first())
+        .copied()
+        .ok_or(Error::UnsupportedFormat(vk::Format::UNDEFINED))?;
+    let present_mode = match vsync {
+        true => vk::PresentModeKHR::FIFO,
+        false => surface_instance
+            .get_physical_device_surface_present_modes(ctx.pdevice, surface)
+            .check("vkGetPhysicalDeviceSurfacePresentModesKHR")?
+            .into_iter()
+            .find(|&mode| {
+                mode == vk::PresentModeKHR::IMMEDIATE || mode == vk::PresentModeKHR::MAILBOX
+            })
+            .unwrap_or(vk::PresentModeKHR::FIFO),
+    };
+    let capabilities = surface_instance
+        .get_physical_device_surface_capabilities(ctx.pdevice, surface)
+        .check("vkGetPhysicalDeviceSurfaceCapabilitiesKHR")?;
+    let extent = swapchain_extent(&capabilities, extent);
+    // Triple buffer where the surface allows it; a max of 0 means no limit.
+    let mut image_count = capabilities.min_image_count.max(3);
+    if capabilities.max_image_count > 0 {
+        image_count = image_count.min(capabilities.max_image_count);
+    }
+
+    let swapchain = ctx
+        .swapchain_device
+        .create_swapchain(
+            &vk::SwapchainCreateInfoKHR::default()
+                .surface(surface)
+                .min_image_count(image_count)
+                .image_format(format.format)
+                .image_color_space(format.color_space)
+                .image_extent(extent)
+                .image_usage(
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+                )
+                .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .pre_transform(capabilities.current_transform)
+                .composite_alpha(vk::CompositeAlphaFlagsKHR::OPAQUE)
+                .present_mode(present_mode)
+                .clipped(true)
+                .image_array_layers(1)
+                .old_swapchain(old_swapchain.unwrap_or_default()),
+            None,
+        )
+        .check("vkCreateSwapchainKHR")?;
+    Ok((Owned::new(ctx, swapchain), format, extent))
+}
+
+// The surface's extent if it has one, otherwise the window size clamped to what it supports.
+fn swapchain_extent(
+    capabilities: &vk::SurfaceCapabilitiesKHR,
+    window: vk::Extent2D,
+) -> vk::Extent2D {
+    if capabilities.current_extent.width != u32::MAX {
+        return capabilities.current_extent;
+    }
+    let (min, max) = (capabilities.min_image_extent, capabilities.max_image_extent);
+    vk::Extent2D {
+        width: window.width.clamp(min.width, max.width),
+        height: window.height.clamp(min.height, max.height),
+    }
+}
+
+unsafe fn create_views(
+    ctx: &Rc<Context>,
+    swapchain: vk::SwapchainKHR,
+    format: vk::Format,
+) -> Result<(Vec<vk::Image>, Vec<ImageView>)> {
+    let images = ctx
+        .swapchain_device
+        .get_swapchain_images(swapchain)
+        .check("vkGetSwapchainImagesKHR")?;
+    let views = images
+        .iter()
+        .map(|&image| {
+            let view = ctx
+                .device
+                .create_image_view(
+                    &vk::ImageViewCreateInfo::default()
+                        .image(image)
+                        .view_type(vk::ImageViewType::TYPE_2D)
+                        .format(format)
+                        .subresource_range(vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        }),
+                    None,
+                )
+                .check("vkCreateImageView")?;
+            Ok(Owned::new(ctx, view))
+        })
+        .collect::<Result<_>>()?;
+    Ok((images, views))
+}
+
+unsafe fn create_offscreen(
+    ctx: &Rc<Context>,
+    extent: vk::Extent2D,
+) -> Result<(Vec<Image>, Vec<ImageView>)> {
+    let mut images = Vec::with_capacity(HEADLESS_IMAGES);
+    let mut views = Vec::with_capacity(HEADLESS_IMAGES);
+    for index in 0..HEADLESS_IMAGES {
+        let image = Image::new(
+            ctx,
+            &vk::ImageCreateInfo::default()
+                .image_type(vk::ImageType::TYPE_2D)
+                .extent(
+                    vk::Extent3D::default()
+                        .width(extent.width)
+                        .height(extent.height)
+                        .depth(1),
+                )
+                .mip_levels(1)
+                .array_layers(1)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .format(HEADLESS_FORMAT)
+                .tiling(vk::ImageTiling::OPTIMAL)
+                .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC),
+            &vk_mem::AllocationCreateInfo {
+                required_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+        )?;
+        ctx.set_name(*image, &format!("offscreen {index}"))?;
+        views.push(image.create_view(
+            vk::ImageViewType::TYPE_2D,
+            HEADLESS_FORMAT,
+            vk::ImageSubresourceRange {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        )?);
+        images.push(image);
+    }
+    Ok((images, views))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extent(width: u32, height: u32) -> vk::Extent2D {
+        vk::Extent2D { width, height }
+    }
+
+    #[test]
+    fn extent_follows_surface() {
+        let capabilities = vk::SurfaceCapabilitiesKHR {
+            current_extent: extent(800, 600),
+            ..Default::default()
+        };
+        assert_eq!(
+            swapchain_extent(&capabilities, extent(1080, 720)),
+            extent(800, 600)
+        );
+    }
+
+    #[test]
+    fn extent_clamps_window() {
+        let capabilities = vk::SurfaceCapabilitiesKHR {
+            current_extent: extent(u32::MAX, u32::MAX),
+            min_image_extent: extent(100, 100),
+            max_image_extent: extent(1000, 1000),
+            ..Default::default()
+        };
+        assert_eq!(
+            swapchain_extent(&capabilities, extent(1080, 720)),
+            extent(1000, 720)
+        );
+        assert_eq!(
+            swapchain_extent(&capabilities, extent(0, 50)),
+            extent(100, 100)
+        );
+    }
+}
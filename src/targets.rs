@@ -0,0 +1,16 @@
+use ash::vk;
+
+/// Format of the scene depth attachment.
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Picks the highest sample count not above `requested` that `supported` allows.
+pub fn clamp_samples(requested: u32, supported: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    [
+        vk::SampleCountFlags::TYPE_8,
+        vk::SampleCountFlags::TYPE_4,
+        vk::SampleCountFlags::TYPE_2,
+    ]
+    .into_iter()
+    .find(|&samples| samples.as_raw() <= requested && supported.contains(samples))
+    .unwrap_or(vk::SampleCountFlags::TYPE_1)
+}
@@ -0,0 +1,235 @@
+use ash::vk;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::context::{CommandPool, Context, Owned, Semaphore, SyncPoint};
+use crate::error::{Check, Result};
+use crate::staging::{Acquires, Staging, StagingBuffer};
+use crate::streaming::{AssetId, UploadQueue};
+
+/// Submits uploads on the transfer queue and hands them over to the graphics queue as they land.
+pub struct Uploader {
+    pub staging_buffer: StagingBuffer,
+    /// Uploads still to be streamed.
+    pub queue: UploadQueue,
+    // Counts completed uploads, so frames can wait on them without stalling the CPU.
+    timeline: Semaphore,
+    value: u64,
+    // Submissions by `submit_all`, which the next frame always waits on.
+    pending: Vec<(u64, Acquires)>,
+    // Streamed uploads in flight, as (timeline value, acquires, completed assets).
+    submissions: VecDeque<(u64, Acquires, Vec<AssetId>)>,
+    // Pending and streamed uploads recorded by the last `acquire`, until it is committed.
+    acquired: (usize, usize),
+    submit_command_buffer: vk::CommandBuffer,
+    // Timeline value of the last `submit_all`.
+    submit_value: u64,
+    // Streaming uploads use one command buffer per frame, free again once its value is reached.
+    stream_command_buffers: Vec<vk::CommandBuffer>,
+    stream_values: Box<[u64]>,
+    _command_pool: CommandPool,
+    ctx: Rc<Context>,
+}
+
+impl Uploader {
+    pub unsafe fn new(ctx: &Rc<Context>, staging_len: u64, frames: usize) -> Result<Self> {
+        let device = &ctx.device;
+        let command_pool = Owned::new(
+            ctx,
+            device
+                .create_command_pool(
+                    &vk::CommandPoolCreateInfo::default()
+                        .queue_family_index(ctx.queues.transfer_family)
+                        .flags(vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER),
+                    None,
+                )
+                .check("vkCreateCommandPool")?,
+        );
+        let mut command_buffers = device
+            .allocate_command_buffers(
+                &vk::CommandBufferAllocateInfo::default()
+                    .command_pool(*command_pool)
+                    .level(vk::CommandBufferLevel::PRIMARY)
+                    .command_buffer_count(1 + frames as u32),
+            )
+            .check("vkAllocateCommandBuffers")?;
+        let submit_command_buffer = command_buffers.remove(0);
+
+        Ok(Self {
+            staging_buffer: StagingBuffer::new(
+                ctx,
+                staging_len,
+                &ctx.instance
+                    .get_physical_device_properties(ctx.pdevice)
+                    .limits,
+            )?,
+            queue: UploadQueue::default(),
+            timeline: ctx.create_timeline_semaphore(0)?,
+            value: 0,
+            pending: Vec::new(),
+            submissions: VecDeque::new(),
+            acquired: (0, 0),
+            submit_command_buffer,
+            submit_value: 0,
+            stream_command_buffers: command_buffers,
+            stream_values: vec![0; frames].into(),
+            _command_pool: command_pool,
+            ctx: ctx.clone(),
+        })
+    }
+
+    /// Counts finished upload submissions.
+    pub fn timeline(&self) -> vk::Semaphore {
+        *self.timeline
+    }
+
+    fn sync_point(&self, value: u64) -> SyncPoint {
+        SyncPoint {
+            semaphore: *self.timeline,
+            value,
+        }
+    }
+
+    /// Submits the uploads `record` stages along with everything queued. This doesn't block; the
+    /// next frame waits for it on the GPU.
+    pub unsafe fn submit_all(
+        &mut self,
+        record: impl FnOnce(&mut Staging) -> Result<()>,
+    ) -> Result<()> {
+        let device = &self.ctx.device;
+        let command_buffer = self.submit_command_buffer;
+        self.sync_point(self.submit_value).wait(device)?;
+        device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .check("vkResetCommandBuffer")?;
+        device
+            .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+            .check("vkBeginCommandBuffer")?;
+
+        // Signaled by the submission below.
+        let done = self.sync_point(self.value + 1);
+        let mut staging = self.staging_buffer.begin_transfer(
+            device,
+            command_buffer,
+            self.ctx.queues.queue_transfer(),
+        )?;
+        record(&mut staging)?;
+        self.queue.record(&mut staging, u64::MAX)?;
+        let acquires = staging.finish(done);
+
+        device
+            .end_command_buffer(command_buffer)
+            .check("vkEndCommandBuffer")?;
+
+        self.submit(command_buffer, done.value)?;
+        self.submit_value = done.value;
+        self.pending.push((done.value, acquires));
+        Ok(())
+    }
+
+    /// Streams a chunk of at most `budget` queued bytes, if `frame`'s last chunk has landed.
+    pub unsafe fn stream(&mut self, frame: usize, budget: u64) -> Result<()> {
+        let device = &self.ctx.device;
+        if self.queue.is_empty()
+            || !self
+                .sync_point(self.stream_values[frame])
+                .is_reached(device)?
+        {
+            return Ok(());
+        }
+
+        let command_buffer = self.stream_command_buffers[frame];
+        device
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())
+            .check("vkResetCommandBuffer")?;
+        device
+            .begin_command_buffer(command_buffer, &vk::CommandBufferBeginInfo::default())
+            .check("vkBeginCommandBuffer")?;
+        // Signaled by the submission below.
+        let done = self.sync_point(self.value + 1);
+        let mut staging = self.staging_buffer.begin_transfer(
+            device,
+            command_buffer,
+            self.ctx.queues.queue_transfer(),
+        )?;
+        let (staged, completed) = self.queue.record(&mut staging, budget)?;
+        let acquires = staging.finish(done);
+        device
+            .end_command_buffer(command_buffer)
+            .check("vkEndCommandBuffer")?;
+
+        if staged > 0 {
+            self.submit(command_buffer, done.value)?;
+            self.stream_values[frame] = done.value;
+            self.submissions
+                .push_back((done.value, acquires, completed));
+        }
+        Ok(())
+    }
+
+    // Submits `command_buffer` on the transfer queue, signalling `value` once it finishes.
+    unsafe fn submit(&mut self, command_buffer: vk::CommandBuffer, value: u64) -> Result<()> {
+        self.ctx
+            .device
+            .queue_submit2(
+                self.ctx.queues.transfer,
+                &[vk::SubmitInfo2::default()
+                    .command_buffer_infos(&[
+                        vk::CommandBufferSubmitInfo::default().command_buffer(command_buffer)
+                    ])
+                    .signal_semaphore_infos(&[vk::SemaphoreSubmitInfo::default()
+                        .semaphore(*self.timeline)
+                        .value(value)
+                        .stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)])],
+                vk::Fence::null(),
+            )
+            .check("vkQueueSubmit2")?;
+        self.value = value;
+        Ok(())
+    }
+
+    /// Takes ownership of finished uploads in `command_buffer`. Returns the timeline value the
+    /// submission has to wait for, and the assets that are now fully uploaded.
+    ///
+    /// Startup data is always waited on; streamed assets are only picked up once their transfer
+    /// has finished.
+    ///
+    /// The uploads are acquired again by the next call until
+    /// [`commit_acquire`](Self::commit_acquire) is called, so a command buffer that is never
+    /// submitted doesn't lose them.
+    pub unsafe fn acquire(
+        &mut self,
+        command_buffer: vk::CommandBuffer,
+    ) -> Result<(Option<u64>, Vec<AssetId>)> {
+        let device = &self.ctx.device;
+        let mut wait = None;
+        for (value, acquires) in &self.pending {
+            acquires.record(device, command_buffer);
+            wait = wait.max(Some(*value));
+        }
+        let uploaded = device
+            .get_semaphore_counter_value(*self.timeline)
+            .check("vkGetSemaphoreCounterValue")?;
+        let mut assets = Vec::new();
+        let mut streamed = 0;
+        for (value, acquires, completed) in &self.submissions {
+            if *value > uploaded {
+                break;
+            }
+            acquires.record(device, command_buffer);
+            wait = wait.max(Some(*value));
+            assets.extend_from_slice(completed);
+            streamed += 1;
+        }
+        self.acquired = (self.pending.len(), streamed);
+        Ok((wait, assets))
+    }
+
+    /// Forgets the uploads the last [`acquire`](Self::acquire) recorded, once its command buffer
+    /// has been submitted.
+    pub fn commit_acquire(&mut self) {
+        let (pending, streamed) = std::mem::take(&mut self.acquired);
+        self.pending.drain(..pending);
+        self.submissions.drain(..streamed);
+    }
+}